@@ -0,0 +1,23 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.10" tiledversion="1.10.2" name="terrain" tilewidth="28" tileheight="32" tilecount="4" columns="4">
+ <image source="terrain.png" width="112" height="32"/>
+ <tile id="0" type="grass">
+  <properties>
+   <property name="color" type="color" value="#ff3c9a3c"/>
+  </properties>
+ </tile>
+ <tile id="1" type="water">
+  <properties>
+   <property name="color" type="color" value="#ff2860c8"/>
+   <property name="movement_cost" type="float" value="3"/>
+   <property name="swimmable" type="bool" value="true"/>
+  </properties>
+ </tile>
+ <tile id="2" type="forest">
+  <properties>
+   <property name="color" type="color" value="#ff1e5a28"/>
+   <property name="opacity" type="float" value="0.6"/>
+   <property name="wood" type="int" value="12"/>
+  </properties>
+ </tile>
+</tileset>
@@ -4,6 +4,14 @@ use crate::position::*;
 use crate::render::*;
 
 
+// walls run from the top of a tile down to this height so pits and cliffs stay closed
+pub const PRISM_FLOOR: f32 = -8.0;
+
+// wall brightness relative to the top, per side starting at the edge from vertex 0 to 1
+pub const WALL_SHADE: [f32; 6] = [0.45, 0.6, 0.75, 0.7, 0.55, 0.4];
+
+const TOP_VERTICES: usize = 6;
+
 pub struct HexTile {
 	pub mesh: Mesh,
 	
@@ -11,6 +19,7 @@ pub struct HexTile {
 	mx_pos: MxPos,
 	screen_pos: TilePos,
 	offset: TilePos,
+	elevation: f32,
 	col: Option<Color>,
 }
 
@@ -24,11 +33,12 @@ impl Drawable for HexTile {
 impl HexTile {
 	pub fn new(mx_pos: MxPos, screen_pos: TilePos, col: Color) -> Self {		
 		let mut hex = HexTile {
-			mesh: create_hex_mesh(col),
-			root: create_hex_mesh(col),
+			mesh: create_hex_prism_mesh(col),
+			root: create_hex_prism_mesh(col),
 			mx_pos,
 			screen_pos,
 			offset: TilePos::ZERO,
+			elevation: 0.0,
 			col: None,
 		};
 		hex.offset_pos(TilePos::ZERO);
@@ -43,7 +53,7 @@ impl HexTile {
 		self.offset = offset;
 		let real = Vec3::from(RealPos::from(self.position()));
 		
-		for i in 0..6 {
+		for i in 0..self.root.vertices.len() {
 			let vx = self.root.vertices[i].position.z + real.z;
 			let vy = self.root.vertices[i].position.x + real.x;
 			self.mesh.vertices[i].position.z = vx;
@@ -51,6 +61,18 @@ impl HexTile {
 		}
 	}
 
+	pub fn set_elevation(&mut self, elevation: f32) {
+		let elevation = elevation.max(PRISM_FLOOR);
+		if elevation == self.elevation { return; }
+		self.elevation = elevation;
+
+		for i in 0..self.mesh.vertices.len() {
+			if is_top_vertex(i) {
+				self.mesh.vertices[i].position.y = self.elevation;
+			}
+		}
+	}
+
 	pub fn position(&self) -> TilePos {
 		&self.screen_pos + &self.offset
 	}
@@ -59,8 +81,11 @@ impl HexTile {
 		self.col = col;
 
 		if let Some(c) = col {
-			for i in 0..6 {	
-				self.mesh.vertices[i].color = c;
+			for i in 0..self.mesh.vertices.len() {
+				self.mesh.vertices[i].color = match i < TOP_VERTICES {
+					true => c,
+					false => shade(c, WALL_SHADE[(i - TOP_VERTICES) / 4]),
+				};
 			}
 		}
 	}
@@ -104,3 +129,35 @@ pub fn create_hex_mesh(color: Color) -> Mesh {
 	    texture: None,
 	}
 }
+
+// the flat hexagon on top followed by a quad per side: (top i, top i+1, floor i+1, floor i)
+pub fn create_hex_prism_mesh(color: Color) -> Mesh {
+	let mut mesh = create_hex_mesh(color);
+
+	for (side, factor) in WALL_SHADE.iter().enumerate() {
+		let a = mesh.vertices[side].position;
+		let b = mesh.vertices[(side + 1) % 6].position;
+		let col = shade(color, *factor);
+		let first = mesh.vertices.len() as u16;
+
+		mesh.vertices.extend_from_slice(&[
+			Vertex { position: a, uv: Vec2::new(0.0, 0.0), color: col },
+			Vertex { position: b, uv: Vec2::new(1.0, 0.0), color: col },
+			Vertex { position: Vec3::new(b.x, PRISM_FLOOR, b.z), uv: Vec2::new(1.0, 1.0), color: col },
+			Vertex { position: Vec3::new(a.x, PRISM_FLOOR, a.z), uv: Vec2::new(0.0, 1.0), color: col },
+		]);
+		mesh.indices.extend_from_slice(&[
+			first, first + 1, first + 2,
+			first, first + 2, first + 3,
+		]);
+	}
+	mesh
+}
+
+fn is_top_vertex(idx: usize) -> bool {
+	idx < TOP_VERTICES || (idx - TOP_VERTICES) % 4 < 2
+}
+
+fn shade(col: Color, factor: f32) -> Color {
+	Color::new(col.r * factor, col.g * factor, col.b * factor, col.a)
+}
@@ -23,16 +23,35 @@ pub trait PerspectiveHandler {
 
 
 #[macroquad::main(window_conf)]
-async fn main() -> Result<(), MapError> {
+async fn main() {
 
+	if let Err(err) = start().await {
+		eprintln!("error: {}", err);
+		std::process::exit(1);
+	}
+}
+
+async fn start() -> Result<(), MapError> {
 	let mut engine = Perspective::new()?;
 	engine.run(Game::new()).await
 }
 
 
+const WORLD_MAP: &str = "./assets/maps/world.toml";
+// a chunked world in this directory is shown instead of the world map
+const WORLD_DIR: &str = "./assets/maps/world";
+
+
 pub struct Perspective {
 	pub gui: Gui::<SpritePointers>,
 	pub scene: Scene,
+	pub watcher: MapWatcher,
+	// off by default, set it to have the run loop save the map periodically
+	pub autosave: Option<Autosave>,
+	// the last failed reload, shown on screen until the file loads again
+	map_error: Option<String>,
+	// the last failed tile update, like a chunk that could not be read
+	tile_error: Option<String>,
 }
 
 impl Perspective {
@@ -41,15 +60,19 @@ impl Perspective {
 	
 		let gui = Gui::<SpritePointers>::new();
 		
-		// let map = Map::new(50, 50)?;
+		// let map = Map::new(50, 50, &GeneratorConfig::read_from_file("./assets/maps/generator.toml")?)?;
 		// map.write_to_file("./assets/maps/world.toml")?;
 		// return Err(MapError::GenericError);
 		
-		let map = Map::read_from_file("./assets/maps/world.toml")?;
+		let map = Map::read_from_file(WORLD_MAP)?;
 		//println!("#MAP: {:?}", map);
-		let scene = Scene::new(map);
+		let mut scene = Scene::new(map);
+		if ChunkedMap::exists(WORLD_DIR) {
+			scene.set_world(Some(ChunkedMap::open(WORLD_DIR)?));
+		}
+		let watcher = MapWatcher::new(WORLD_MAP);
 		
-		Ok(Perspective { gui, scene })
+		Ok(Perspective { gui, scene, watcher, autosave: None, map_error: None, tile_error: None })
 	}
 
 	pub async fn run<T>(&mut self, mut game: T) -> Result<(), MapError> 
@@ -65,18 +88,67 @@ impl Perspective {
 			game.update_scene(&mut self.scene);
 			game.update_gui(&mut self.gui);
 
-			// pre-draw update
-			self.scene.update_floor_tiles();
+			// hot-reload, the camera and lights stay as they are
+			match self.watcher.poll() {
+				Some(Ok(map)) => {
+					self.scene.set_map(map);
+					self.map_error = None;
+					if let Some(autosave) = &mut self.autosave {
+						autosave.reset(&self.scene.map);
+					}
+				},
+				Some(Err(err)) => self.map_error = Some(err.to_string()),
+				None => {},
+			}
+
+			if let Some(autosave) = &mut self.autosave {
+				match autosave.tick(&self.scene.map) {
+					// NOTE: our own save should not trigger a reload when the watched file is saved
+					Ok(true) if autosave.path() == self.watcher.path() => self.watcher.sync(),
+					Ok(_) => {},
+					Err(err) => self.map_error = Some(err.to_string()),
+				}
+			}
+
+			// pre-draw update, a failed update is retried next frame
+			match self.scene.update_floor_tiles() {
+				Ok(()) => self.tile_error = None,
+				Err(err) => {
+					self.scene.invalidate_tiles();
+					self.tile_error = Some(err.to_string());
+				},
+			}
 			
 			// draw
 			clear_background(LIGHTGRAY);       
 	        self.scene.draw();
 	        self.gui.draw();
+	        self.draw_map_error();
 	        
 	        next_frame().await
 	    }
+
+	    if let Some(world) = &mut self.scene.world {
+	    	world.flush()?;
+	    }
 	    Ok(())
 	}
+
+	fn draw_map_error(&self) {
+		let lines: Vec<&str> = self.map_error.iter()
+			.chain(&self.tile_error)
+			.flat_map(|x| x.lines())
+			.collect();
+		if lines.is_empty() { return; }
+
+		let font_size = 20.0;
+		let height = lines.len() as f32 * font_size + font_size * 0.5;
+
+		draw_rectangle(0.0, 0.0, screen_width(), height, Color::new(0.0, 0.0, 0.0, 0.75));
+		for (i, line) in lines.iter().enumerate() {
+			draw_text(line, 8.0, (i + 1) as f32 * font_size, font_size, RED);
+		}
+	}
 }
 
 pub struct Game {
@@ -91,6 +163,12 @@ impl Game {
 	}
 }
 
+impl Default for Game {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl PerspectiveHandler for Game {
 
 	fn initialize(&mut self, scene: &mut Scene, gui: &mut Gui<SpritePointers>) {
@@ -0,0 +1,464 @@
+use std::path::Path;
+use serde::*;
+use crate::*;
+
+
+pub const BINARY_MAGIC: &[u8; 4] = b"PMAP";
+pub const BINARY_VERSION: u16 = 3;
+
+const HEADER_SIZE: usize = 11;
+const ENCODING_PACKED: u8 = 0;
+const ENCODING_RLE: u8 = 1;
+const BYTES_SOURCE: &str = "<bytes>";
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapFormat {
+	Toml,
+	Binary,
+}
+
+impl MapFormat {
+	pub fn detect(bytes: &[u8]) -> MapFormat {
+		match bytes.starts_with(BINARY_MAGIC) {
+			true => MapFormat::Binary,
+			false => MapFormat::Toml,
+		}
+	}
+
+	pub fn from_path(file_path: &str) -> MapFormat {
+		match Path::new(file_path).extension().and_then(|x| x.to_str()) {
+			Some("pmap") | Some("bin") => MapFormat::Binary,
+			_ => MapFormat::Toml,
+		}
+	}
+}
+
+
+// layout (little endian):
+// magic [u8; 4] | version u16 | width i16 | height i16 | encoding u8 | payload_len u32 | payload
+//   | layer_count u16 | layers | extras_len u32 | extras toml
+//
+// packed payload => [r, g, b, a] for every cell
+// rle payload    => (run u16, [r, g, b, a]) until all cells are covered
+// layer          => name_len u16 | name utf8 | kind u8 | one value per cell
+//
+// NOTE: version 1 files have no payload_len and no layers, the payload runs to the end,
+// version 2 files have no extras
+
+// everything that is not stored per cell is embedded as a small toml document
+#[derive(Default, Serialize, Deserialize)]
+struct BinaryExtras {
+	#[serde(default)]
+	header: MapHeader,
+	#[serde(default, skip_serializing_if = "Palette::is_empty")]
+	palette: Palette,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	objects: Vec<MapObject>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	cell_data: Vec<CellData>,
+}
+
+impl Map {
+	pub fn to_bytes(&self) -> Result<Vec<u8>, MapError> {
+		let packed = encode_packed(&self.matrix);
+		let rle = encode_rle(&self.matrix);
+
+		let (encoding, payload) = match rle.len() < packed.len() {
+			true => (ENCODING_RLE, rle),
+			false => (ENCODING_PACKED, packed),
+		};
+
+		let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.len());
+		bytes.extend_from_slice(BINARY_MAGIC);
+		bytes.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+		bytes.extend_from_slice(&self.width.to_le_bytes());
+		bytes.extend_from_slice(&self.height.to_le_bytes());
+		bytes.push(encoding);
+		bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(&payload);
+
+		bytes.extend_from_slice(&(self.layers.len() as u16).to_le_bytes());
+		for layer in &self.layers {
+			encode_layer(&mut bytes, layer);
+		}
+
+		let extras = BinaryExtras {
+			header: self.header.clone(),
+			palette: self.palette.clone(),
+			objects: self.objects.clone(),
+			cell_data: self.cell_data.clone(),
+		};
+		let extras = toml::to_string(&extras)
+			.map_err(|x| MapError::SerializeFailed(x.to_string()))?;
+		bytes.extend_from_slice(&(extras.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(extras.as_bytes());
+		Ok(bytes)
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Map, MapError> {
+		if bytes.len() < HEADER_SIZE || !bytes.starts_with(BINARY_MAGIC) {
+			return Err(parse_error("missing or truncated binary header"));
+		}
+
+		let mut reader = ByteReader::new(&bytes[BINARY_MAGIC.len()..]);
+		let version = reader.u16()?;
+		if version == 0 || version > BINARY_VERSION {
+			return Err(MapError::UnsupportedVersion {
+				path: BYTES_SOURCE.to_string(),
+				found: version as i64,
+				supported: BINARY_VERSION as u32,
+			});
+		}
+
+		let width = reader.u16()? as i16;
+		let height = reader.u16()? as i16;
+		if width < 0 || height < 0 {
+			return Err(parse_error("negative map dimensions"));
+		}
+
+		let cells = width as usize * height as usize;
+		let encoding = reader.u8()?;
+		let payload = match version {
+			1 => reader.rest(),
+			_ => {
+				let len = reader.u32()? as usize;
+				reader.take(len)?
+			},
+		};
+
+		let colors = match encoding {
+			ENCODING_PACKED => decode_packed(payload, cells)?,
+			ENCODING_RLE => decode_rle(payload, cells)?,
+			_ => return Err(parse_error("unknown cell encoding")),
+		};
+
+		let matrix = colors.into_iter()
+			.enumerate()
+			.map(|(i, color)| MapValue {
+				pos: [(i % width as usize) as u16, (i / width as usize) as u16],
+				color,
+			})
+			.collect();
+
+		let mut layers = Vec::new();
+		if version > 1 {
+			for _ in 0..reader.u16()? {
+				layers.push(decode_layer(&mut reader, cells)?);
+			}
+		}
+
+		let mut extras = BinaryExtras::default();
+		if version > 2 {
+			let len = reader.u32()? as usize;
+			let toml = std::str::from_utf8(reader.take(len)?)
+				.map_err(|_x| parse_error("extras are not valid utf8"))?;
+			let toml = Map::migrate(BYTES_SOURCE, toml)?;
+			extras = toml::from_str(&toml)
+				.map_err(|x| MapError::toml_failed(BYTES_SOURCE, &toml, &x))?;
+		}
+
+		Ok(Map {
+			width,
+			height,
+			header: extras.header,
+			palette: extras.palette,
+			layers,
+			matrix,
+			objects: extras.objects,
+			cell_data: extras.cell_data,
+			dirty: Default::default(),
+			history: Default::default(),
+			revision: 0,
+		})
+	}
+}
+
+
+struct ByteReader<'a> {
+	bytes: &'a [u8],
+	cursor: usize,
+}
+
+impl<'a> ByteReader<'a> {
+	fn new(bytes: &'a [u8]) -> Self {
+		ByteReader { bytes, cursor: 0 }
+	}
+
+	fn take(&mut self, len: usize) -> Result<&'a [u8], MapError> {
+		if self.cursor + len > self.bytes.len() {
+			return Err(parse_error("unexpected end of binary data"));
+		}
+		let slice = &self.bytes[self.cursor..self.cursor + len];
+		self.cursor += len;
+		Ok(slice)
+	}
+
+	fn rest(&mut self) -> &'a [u8] {
+		let slice = &self.bytes[self.cursor..];
+		self.cursor = self.bytes.len();
+		slice
+	}
+
+	fn u8(&mut self) -> Result<u8, MapError> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u16(&mut self) -> Result<u16, MapError> {
+		let b = self.take(2)?;
+		Ok(u16::from_le_bytes([b[0], b[1]]))
+	}
+
+	fn u32(&mut self) -> Result<u32, MapError> {
+		let b = self.take(4)?;
+		Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+	}
+}
+
+
+fn parse_error(message: &str) -> MapError {
+	MapError::parse_failed(BYTES_SOURCE, message)
+}
+
+pub(super) fn encode_packed(matrix: &[MapValue]) -> Vec<u8> {
+	let mut payload = Vec::with_capacity(matrix.len() * 4);
+	for value in matrix {
+		payload.extend_from_slice(&value.color);
+	}
+	payload
+}
+
+fn encode_rle(matrix: &[MapValue]) -> Vec<u8> {
+	let mut payload = Vec::new();
+	let mut iter = matrix.iter().peekable();
+
+	while let Some(value) = iter.next() {
+		let mut run: u16 = 1;
+		while run < u16::MAX && iter.peek().map(|x| x.color) == Some(value.color) {
+			iter.next();
+			run += 1;
+		}
+		payload.extend_from_slice(&run.to_le_bytes());
+		payload.extend_from_slice(&value.color);
+	}
+	payload
+}
+
+fn decode_packed(payload: &[u8], cells: usize) -> Result<Vec<[u8; 4]>, MapError> {
+	if payload.len() != cells * 4 {
+		return Err(parse_error("packed payload does not match map dimensions"));
+	}
+
+	Ok(payload.chunks_exact(4)
+		.map(|c| [c[0], c[1], c[2], c[3]])
+		.collect())
+}
+
+fn decode_rle(payload: &[u8], cells: usize) -> Result<Vec<[u8; 4]>, MapError> {
+	if !payload.len().is_multiple_of(6) {
+		return Err(parse_error("truncated run-length payload"));
+	}
+
+	// NOTE: the header dimensions are not trusted, a corrupt file could claim far more cells than its runs cover
+	let mut colors = Vec::with_capacity(cells.min(payload.len() / 6 * u16::MAX as usize));
+	for c in payload.chunks_exact(6) {
+		let run = u16::from_le_bytes([c[0], c[1]]) as usize;
+		if colors.len() + run > cells {
+			return Err(parse_error("run-length payload exceeds map dimensions"));
+		}
+		colors.extend(std::iter::repeat_n([c[2], c[3], c[4], c[5]], run));
+	}
+
+	match colors.len() == cells {
+		true => Ok(colors),
+		false => Err(parse_error("run-length payload does not cover every cell")),
+	}
+}
+
+fn kind_to_byte(kind: LayerKind) -> u8 {
+	match kind {
+		LayerKind::U8 => 0,
+		LayerKind::U16 => 1,
+		LayerKind::F32 => 2,
+		LayerKind::Color => 3,
+		LayerKind::Bool => 4,
+	}
+}
+
+fn kind_from_byte(byte: u8) -> Result<LayerKind, MapError> {
+	match byte {
+		0 => Ok(LayerKind::U8),
+		1 => Ok(LayerKind::U16),
+		2 => Ok(LayerKind::F32),
+		3 => Ok(LayerKind::Color),
+		4 => Ok(LayerKind::Bool),
+		_ => Err(parse_error("unknown layer kind")),
+	}
+}
+
+pub(super) fn encode_layer(bytes: &mut Vec<u8>, layer: &MapLayer) {
+	bytes.extend_from_slice(&(layer.name.len() as u16).to_le_bytes());
+	bytes.extend_from_slice(layer.name.as_bytes());
+	bytes.push(kind_to_byte(layer.kind()));
+
+	match &layer.data {
+		LayerData::U8(v) => bytes.extend_from_slice(v),
+		LayerData::U16(v) => v.iter().for_each(|x| bytes.extend_from_slice(&x.to_le_bytes())),
+		LayerData::F32(v) => v.iter().for_each(|x| bytes.extend_from_slice(&x.to_le_bytes())),
+		LayerData::Color(v) => v.iter().for_each(|x| bytes.extend_from_slice(x)),
+		LayerData::Bool(v) => v.iter().for_each(|x| bytes.push(*x as u8)),
+	}
+}
+
+fn decode_layer(reader: &mut ByteReader, cells: usize) -> Result<MapLayer, MapError> {
+	let name_len = reader.u16()? as usize;
+	let name = std::str::from_utf8(reader.take(name_len)?)
+		.map_err(|_x| parse_error("layer name is not valid utf8"))?
+		.to_string();
+
+	let data = match kind_from_byte(reader.u8()?)? {
+		LayerKind::U8 => LayerData::U8(reader.take(cells)?.to_vec()),
+		LayerKind::U16 => LayerData::U16(reader.take(cells * 2)?
+			.chunks_exact(2)
+			.map(|c| u16::from_le_bytes([c[0], c[1]]))
+			.collect()),
+		LayerKind::F32 => LayerData::F32(reader.take(cells * 4)?
+			.chunks_exact(4)
+			.map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+			.collect()),
+		LayerKind::Color => LayerData::Color(reader.take(cells * 4)?
+			.chunks_exact(4)
+			.map(|c| [c[0], c[1], c[2], c[3]])
+			.collect()),
+		LayerKind::Bool => LayerData::Bool(reader.take(cells)?
+			.iter()
+			.map(|x| *x != 0)
+			.collect()),
+	};
+
+	Ok(MapLayer { name, data })
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::position::*;
+
+	const ENCODING_OFFSET: usize = 10;
+
+	// a map using every part of the format, `noisy` gives each cell its own colour so the
+	// packed encoding wins over run-length
+	fn sample_map(noisy: bool) -> Map {
+		let mut map = Map::filled(9, 7, &GREEN).unwrap();
+		if noisy {
+			for mx in map.positions().collect::<Vec<_>>() {
+				map.set_at_mx(&mx, &Color::from_rgba(mx.hor as u8 * 20, mx.ver as u8 * 30, 90, 255)).unwrap();
+			}
+		}
+
+		map.palette_mut().define(TerrainType::new("grass", [60, 160, 60, 255])).unwrap();
+		map.palette_mut().define(TerrainType { movement_cost: 0.0, ..TerrainType::new("water", [30, 70, 160, 255]) }).unwrap();
+		map.add_layer(TERRAIN_LAYER, LayerKind::U16).unwrap();
+		map.add_layer(ELEVATION_LAYER, LayerKind::F32).unwrap();
+		map.add_layer("fog", LayerKind::Bool).unwrap();
+		map.set_terrain_at_mx(&MxPos::new(3, 2), "water").unwrap();
+		map.set_layer_at_mx(ELEVATION_LAYER, &MxPos::new(4, 4), LayerValue::F32(0.375)).unwrap();
+		map.set_layer_at_mx("fog", &MxPos::new(8, 6), LayerValue::Bool(true)).unwrap();
+
+		map.header_mut().name = "sample".to_string();
+		map.header_mut().metadata.insert("difficulty".to_string(), toml::Value::Integer(3));
+		map.set_spawn_point("start", &MxPos::new(1, 1)).unwrap();
+		map.add_object(MapObject { kind: "chest".to_string(), ..MapObject::new("loot", &MxPos::new(5, 3)) }).unwrap();
+		map.set_cell_property(&MxPos::new(2, 5), "gold", 12).unwrap();
+		map.tag_cell(&MxPos::new(2, 5), "hidden").unwrap();
+		map
+	}
+
+	fn toml_of(map: &Map) -> String {
+		toml::to_string(map).unwrap()
+	}
+
+	fn round_trip(noisy: bool, encoding: u8) {
+		let toml = toml_of(&sample_map(noisy));
+		let map: Map = toml::from_str(&toml).unwrap();
+
+		let bytes = map.to_bytes().unwrap();
+		assert_eq!(bytes[ENCODING_OFFSET], encoding);
+		assert_eq!(MapFormat::detect(&bytes), MapFormat::Binary);
+
+		let back = Map::from_bytes(&bytes).unwrap();
+		assert_eq!(toml_of(&back), toml);
+		assert_eq!(back.to_bytes().unwrap(), bytes);
+	}
+
+	#[test]
+	fn round_trips_run_length_maps() {
+		round_trip(false, ENCODING_RLE);
+	}
+
+	#[test]
+	fn round_trips_packed_maps() {
+		round_trip(true, ENCODING_PACKED);
+	}
+
+	#[test]
+	fn rejects_truncated_data() {
+		for noisy in [false, true] {
+			let bytes = sample_map(noisy).to_bytes().unwrap();
+			for len in 0..bytes.len() {
+				assert!(Map::from_bytes(&bytes[..len]).is_err(), "{} of {} bytes parsed", len, bytes.len());
+			}
+		}
+	}
+
+	#[test]
+	fn rejects_corrupt_data() {
+		let bytes = sample_map(false).to_bytes().unwrap();
+		let corrupt = |offset: usize, value: u8| {
+			let mut bytes = bytes.clone();
+			bytes[offset] = value;
+			Map::from_bytes(&bytes)
+		};
+
+		assert!(matches!(corrupt(0, b'X'), Err(MapError::ParseFileFailed { .. })));
+		assert!(matches!(corrupt(ENCODING_OFFSET, 7), Err(MapError::ParseFileFailed { .. })));
+		// a run longer than the map
+		assert!(matches!(corrupt(HEADER_SIZE + 4 + 1, 0xff), Err(MapError::ParseFileFailed { .. })));
+		// a negative height
+		assert!(matches!(corrupt(9, 0x80), Err(MapError::ParseFileFailed { .. })));
+		// the extras are the last bytes, an unfinished toml document
+		assert!(matches!(corrupt(bytes.len() - 1, b'['), Err(MapError::ParseFileFailed { .. })));
+	}
+
+	#[test]
+	fn oversized_headers_are_rejected() {
+		let mut bytes = Vec::new();
+		bytes.extend_from_slice(BINARY_MAGIC);
+		bytes.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+		bytes.extend_from_slice(&i16::MAX.to_le_bytes());
+		bytes.extend_from_slice(&i16::MAX.to_le_bytes());
+		bytes.push(ENCODING_RLE);
+		bytes.extend_from_slice(&6u32.to_le_bytes());
+		bytes.extend_from_slice(&[1, 0, 1, 2, 3, 255]);
+
+		assert!(matches!(Map::from_bytes(&bytes), Err(MapError::ParseFileFailed { .. })));
+		assert_eq!(decode_rle(&[], 1 << 30).unwrap_err().to_string(), parse_error("run-length payload does not cover every cell").to_string());
+		assert_eq!(decode_rle(&[2, 0, 1, 2, 3, 255], 2).unwrap(), vec![[1, 2, 3, 255]; 2]);
+	}
+
+	#[test]
+	fn newer_versions_are_unsupported() {
+		let mut bytes = sample_map(false).to_bytes().unwrap();
+		bytes[4..6].copy_from_slice(&(BINARY_VERSION + 1).to_le_bytes());
+
+		match Map::from_bytes(&bytes).map_err(|x| x.at_path("newer.pmap")) {
+			Err(MapError::UnsupportedVersion { path, found, supported }) => {
+				assert_eq!(path, "newer.pmap");
+				assert_eq!(found, BINARY_VERSION as i64 + 1);
+				assert_eq!(supported, BINARY_VERSION as u32);
+			},
+			other => panic!("expected an unsupported version, got {:?}", other.map(|_| ())),
+		}
+	}
+}
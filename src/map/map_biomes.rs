@@ -0,0 +1,187 @@
+use std::{fs};
+use serde::*;
+use crate::position::*;
+use super::*;
+
+
+// a terrain that is picked when all three samples of a cell fall within its ranges
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Biome {
+	#[serde(flatten)]
+	pub terrain: TerrainType,
+	#[serde(default = "full_range")]
+	pub elevation: [f32; 2],
+	#[serde(default = "full_range")]
+	pub moisture: [f32; 2],
+	#[serde(default = "full_range")]
+	pub temperature: [f32; 2],
+}
+
+fn full_range() -> [f32; 2] {
+	[0.0, 1.0]
+}
+
+impl Biome {
+	pub fn new(name: &str, color: [u8; 4]) -> Self {
+		Biome {
+			terrain: TerrainType::new(name, color),
+			elevation: full_range(),
+			moisture: full_range(),
+			temperature: full_range(),
+		}
+	}
+
+	pub fn matches(&self, sample: &BiomeSample) -> bool {
+		let within = |range: &[f32; 2], value: f32| value >= range[0] && value <= range[1];
+		within(&self.elevation, sample.elevation)
+			&& within(&self.moisture, sample.moisture)
+			&& within(&self.temperature, sample.temperature)
+	}
+
+	fn with_ranges(mut self, elevation: [f32; 2], moisture: [f32; 2], temperature: [f32; 2]) -> Self {
+		self.elevation = elevation;
+		self.moisture = moisture;
+		self.temperature = temperature;
+		self
+	}
+
+	fn with_cost(mut self, movement_cost: f32) -> Self {
+		self.terrain.movement_cost = movement_cost;
+		self
+	}
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiomeSample {
+	pub elevation: f32,
+	pub moisture: f32,
+	pub temperature: f32,
+}
+
+
+// biomes are tried in order and the first match wins, cells matching none get the last biome
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BiomeConfig {
+	// added to the seed of every field, so one number reshuffles the whole world
+	pub seed: u32,
+	pub elevation: NoiseSettings,
+	pub moisture: NoiseSettings,
+	pub temperature: NoiseSettings,
+	// cells below sea level are flat, the highest cells end up this many units above it,
+	// 0.0 leaves the elevation layer alone
+	pub sea_level: f32,
+	pub height_scale: f32,
+	pub biomes: Vec<Biome>,
+}
+
+impl Default for BiomeConfig {
+	fn default() -> Self {
+		let field = NoiseSettings { function: NoiseFunction::Fbm, octaves: 5, frequency: 0.06, ..NoiseSettings::default() };
+		BiomeConfig {
+			seed: 0,
+			elevation: field.clone(),
+			moisture: NoiseSettings { frequency: 0.04, ..field.clone() },
+			temperature: NoiseSettings { octaves: 3, frequency: 0.03, ..field },
+			sea_level: 0.45,
+			height_scale: 0.0,
+			biomes: vec![
+				Biome::new("ocean", [30, 70, 160, 255]).with_ranges([0.0, 0.45], full_range(), full_range()).with_cost(0.0),
+				Biome::new("beach", [220, 205, 145, 255]).with_ranges([0.45, 0.5], full_range(), full_range()),
+				Biome::new("mountain", [130, 120, 115, 255]).with_ranges([0.8, 1.0], full_range(), full_range()).with_cost(3.0),
+				Biome::new("tundra", [205, 215, 220, 255]).with_ranges(full_range(), full_range(), [0.0, 0.35]).with_cost(1.5),
+				Biome::new("desert", [225, 190, 110, 255]).with_ranges(full_range(), [0.0, 0.4], [0.6, 1.0]).with_cost(1.5),
+				Biome::new("forest", [40, 115, 50, 255]).with_ranges(full_range(), [0.55, 1.0], full_range()).with_cost(2.0),
+				Biome::new("grassland", [110, 170, 75, 255]),
+			],
+		}
+	}
+}
+
+impl BiomeConfig {
+	pub fn read_from_file(file_path: &str) -> Result<BiomeConfig, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))
+	}
+
+	pub fn with_seed(&self, seed: u32) -> Self {
+		BiomeConfig { seed, ..self.clone() }
+	}
+
+	// NOTE: each field is offset by its position as well, so fields with the same settings still differ
+	fn fields(&self) -> [NoiseSettings; 3] {
+		let field = |n: u32, x: &NoiseSettings| x.with_seed(x.seed.wrapping_add(self.seed).wrapping_add(n * 7919));
+		[field(0, &self.elevation), field(1, &self.moisture), field(2, &self.temperature)]
+	}
+
+	pub fn biome_for(&self, sample: &BiomeSample) -> Option<usize> {
+		self.biomes.iter().position(|x| x.matches(sample))
+			.or_else(|| self.biomes.len().checked_sub(1))
+	}
+}
+
+
+impl Map {
+	pub fn with_biomes(width: usize, height: usize, config: &BiomeConfig) -> Result<Map, MapError> {
+		let mut map = Map::filled(width, height, &GRAY)?;
+		map.generate_biomes(config)?;
+		Ok(map)
+	}
+
+	// replaces the palette with the biomes and paints every cell with the biome it samples,
+	// like resizing this bypasses and clears the undo history
+	pub fn generate_biomes(&mut self, config: &BiomeConfig) -> Result<(), MapError> {
+		if config.biomes.is_empty() {
+			return Err(MapError::InvalidConfig("no biomes defined".to_string()));
+		}
+
+		let mut palette = Palette::new();
+		for biome in &config.biomes {
+			palette.define(biome.terrain.clone())?;
+		}
+
+		let fields = config.fields();
+		let noise = fields.each_ref().map(|x| x.build());
+		let width = self.width.max(0) as i32;
+		let len = self.matrix.len();
+
+		let mut terrain = vec![0u16; len];
+		let mut heights = vec![0f32; len];
+		for (idx, value) in self.matrix.iter_mut().enumerate() {
+			let mx = MxPos::new(idx as i32 % width, idx as i32 / width);
+			let sample = BiomeSample {
+				elevation: fields[0].sample(noise[0].as_ref(), &mx),
+				moisture: fields[1].sample(noise[1].as_ref(), &mx),
+				temperature: fields[2].sample(noise[2].as_ref(), &mx),
+			};
+
+			let biome = config.biome_for(&sample).unwrap_or(0);
+			terrain[idx] = palette.index_of(&config.biomes[biome].terrain.name).unwrap_or(0);
+			value.color = config.biomes[biome].terrain.color;
+
+			let above_sea = (sample.elevation - config.sea_level).max(0.0) / (1.0 - config.sea_level).max(f32::EPSILON);
+			heights[idx] = above_sea * config.height_scale;
+		}
+
+		self.palette = palette;
+		self.replace_layer(TERRAIN_LAYER, LayerData::U16(terrain));
+		if config.height_scale > 0.0 {
+			self.replace_layer(ELEVATION_LAYER, LayerData::F32(heights));
+		}
+		self.clear_history();
+		self.mark_all_dirty();
+		Ok(())
+	}
+
+	// swaps in generated data for a whole layer, bypassing the history
+	pub(super) fn replace_layer(&mut self, name: &str, data: LayerData) {
+		match self.layer_mut(name) {
+			Some(layer) => layer.data = data,
+			None => self.layers.push(MapLayer { name: name.to_string(), data }),
+		}
+	}
+}
@@ -0,0 +1,363 @@
+use std::{fs};
+use std::path::Path;
+use std::collections::HashMap;
+use serde::*;
+use macroquad::prelude::*;
+use crate::position::*;
+use super::*;
+
+
+pub const DEFAULT_CHUNK_CACHE: usize = 64;
+
+const MANIFEST_FILE: &str = "world.toml";
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkPos {
+	pub hor: i32,
+	pub ver: i32,
+}
+
+impl ChunkPos {
+	pub fn new(hor: i32, ver: i32) -> Self {
+		ChunkPos { hor, ver }
+	}
+
+	// the chunk holding the cell and the cell position inside of that chunk
+	pub fn split(mx: &MxPos, chunk_size: u16) -> (ChunkPos, MxPos) {
+		let size = chunk_size as i32;
+		(
+			ChunkPos::new(mx.hor.div_euclid(size), mx.ver.div_euclid(size)),
+			MxPos::new(mx.hor.rem_euclid(size), mx.ver.rem_euclid(size)),
+		)
+	}
+
+	pub fn origin(&self, chunk_size: u16) -> MxPos {
+		MxPos::new(self.hor * chunk_size as i32, self.ver * chunk_size as i32)
+	}
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct WorldManifest {
+	chunk_size: u16,
+}
+
+// a chunk needs saving once its map moved past the revision it was loaded or saved at
+struct CachedChunk {
+	map: Option<Map>,
+	saved_revision: u64,
+	last_used: u64,
+}
+
+impl CachedChunk {
+	fn is_dirty(&self) -> bool {
+		self.map.as_ref().is_some_and(|x| x.revision() != self.saved_revision)
+	}
+}
+
+
+// a world stored as a directory of square chunk maps, chunks are read when first
+// touched and the least recently used ones are dropped (and saved when dirty)
+// once more than `capacity` chunks are loaded
+pub struct ChunkedMap {
+	dir: String,
+	chunk_size: u16,
+	capacity: usize,
+	tick: u64,
+	cache: HashMap<ChunkPos, CachedChunk>,
+}
+
+impl ChunkedMap {
+	pub fn create(dir: &str, chunk_size: u16) -> Result<Self, MapError> {
+		check_chunk_size(chunk_size)?;
+		fs::create_dir_all(dir)
+			.map_err(|x| MapError::write_failed(dir, x))?;
+
+		let manifest_path = manifest_path(dir);
+		let toml = toml::to_string(&WorldManifest { chunk_size })
+			.map_err(|x| MapError::SerializeFailed(x.to_string()))?;
+		write_atomic(&manifest_path, toml.as_bytes())?;
+
+		Ok(ChunkedMap::with_size(dir, chunk_size))
+	}
+
+	pub fn exists(dir: &str) -> bool {
+		Path::new(&manifest_path(dir)).is_file()
+	}
+
+	pub fn open(dir: &str) -> Result<Self, MapError> {
+		let manifest_path = manifest_path(dir);
+		let toml = fs::read_to_string(&manifest_path)
+			.map_err(|x| MapError::read_failed(&manifest_path, x))?;
+
+		let manifest: WorldManifest = toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(&manifest_path, &toml, &x))?;
+
+		check_chunk_size(manifest.chunk_size)?;
+		Ok(ChunkedMap::with_size(dir, manifest.chunk_size))
+	}
+
+	fn with_size(dir: &str, chunk_size: u16) -> Self {
+		ChunkedMap {
+			dir: dir.to_string(),
+			chunk_size,
+			capacity: DEFAULT_CHUNK_CACHE,
+			tick: 0,
+			cache: HashMap::new(),
+		}
+	}
+
+	pub fn chunk_size(&self) -> u16 {
+		self.chunk_size
+	}
+
+	pub fn set_capacity(&mut self, capacity: usize) -> Result<(), MapError> {
+		self.capacity = capacity.max(1);
+		while self.cache.len() > self.capacity {
+			self.evict()?;
+		}
+		Ok(())
+	}
+
+	pub fn loaded_chunks(&self) -> usize {
+		self.cache.len()
+	}
+
+	pub fn is_loaded(&self, pos: &ChunkPos) -> bool {
+		self.cache.contains_key(pos)
+	}
+
+	// loaded and changed since it was last saved
+	pub fn is_dirty(&self, pos: &ChunkPos) -> bool {
+		self.cache.get(pos).is_some_and(|x| x.is_dirty())
+	}
+
+	pub fn chunk_path(&self, pos: &ChunkPos) -> String {
+		Path::new(&self.dir)
+			.join(format!("chunk_{}_{}.pmap", pos.hor, pos.ver))
+			.to_string_lossy()
+			.to_string()
+	}
+
+	// None when the chunk was never created
+	pub fn chunk(&mut self, pos: &ChunkPos) -> Result<Option<&Map>, MapError> {
+		self.touch(pos)?;
+		Ok(self.cache.get(pos).and_then(|x| x.map.as_ref()))
+	}
+
+	// creates a blank gray chunk when it does not exist yet, a chunk is only saved on
+	// eviction or flush when something was written to it
+	pub fn chunk_mut(&mut self, pos: &ChunkPos) -> Result<&mut Map, MapError> {
+		self.touch(pos)?;
+		let size = self.chunk_size as usize;
+		let cached = self.cache.get_mut(pos).unwrap();
+
+		if cached.map.is_none() {
+			let mut map = Map::filled(size, size, &GRAY)?;
+			map.mark_all_dirty();
+			cached.saved_revision = map.revision();
+			cached.map = Some(map);
+		}
+		Ok(cached.map.as_mut().unwrap())
+	}
+
+	pub fn get_at_mx(&mut self, mx: &MxPos) -> Result<Option<Color>, MapError> {
+		let (chunk, local) = ChunkPos::split(mx, self.chunk_size);
+		Ok(self.chunk(&chunk)?.and_then(|x| x.terrain_color_at_mx(&local)))
+	}
+
+	pub fn set_color_at_mx(&mut self, mx: &MxPos, color: &Color) -> Result<(), MapError> {
+		let (chunk, local) = ChunkPos::split(mx, self.chunk_size);
+		self.chunk_mut(&chunk)?.set_at_mx(&local, color)
+	}
+
+	pub fn elevation_at_mx(&mut self, mx: &MxPos) -> Result<f32, MapError> {
+		let (chunk, local) = ChunkPos::split(mx, self.chunk_size);
+		Ok(self.chunk(&chunk)?.map(|x| x.elevation_at_mx(&local)).unwrap_or(0.0))
+	}
+
+	pub fn flush(&mut self) -> Result<(), MapError> {
+		let dirty: Vec<ChunkPos> = self.cache.iter()
+			.filter(|(_, x)| x.is_dirty())
+			.map(|(pos, _)| *pos)
+			.collect();
+
+		for pos in dirty {
+			self.save(&pos)?;
+		}
+		Ok(())
+	}
+
+	fn touch(&mut self, pos: &ChunkPos) -> Result<(), MapError> {
+		self.tick += 1;
+
+		if let Some(cached) = self.cache.get_mut(pos) {
+			cached.last_used = self.tick;
+			return Ok(());
+		}
+
+		while self.cache.len() >= self.capacity {
+			self.evict()?;
+		}
+
+		let path = self.chunk_path(pos);
+		let map = match Path::new(&path).exists() {
+			true => Some(Map::read_from_file(&path)?),
+			false => None,
+		};
+
+		let saved_revision = map.as_ref().map(|x| x.revision()).unwrap_or(0);
+		self.cache.insert(*pos, CachedChunk { map, saved_revision, last_used: self.tick });
+		Ok(())
+	}
+
+	fn evict(&mut self) -> Result<(), MapError> {
+		let oldest = self.cache.iter()
+			.min_by_key(|(_, x)| x.last_used)
+			.map(|(pos, _)| *pos);
+
+		if let Some(pos) = oldest {
+			self.save(&pos)?;
+			self.cache.remove(&pos);
+		}
+		Ok(())
+	}
+
+	fn save(&mut self, pos: &ChunkPos) -> Result<(), MapError> {
+		let path = self.chunk_path(pos);
+		if let Some(cached) = self.cache.get_mut(pos).filter(|x| x.is_dirty()) {
+			if let Some(map) = &cached.map {
+				map.write_to_file(&path)?;
+				cached.saved_revision = map.revision();
+			}
+		}
+		Ok(())
+	}
+}
+
+impl TileSource for ChunkedMap {
+	fn tile_color(&mut self, mx: &MxPos) -> Result<Option<Color>, MapError> {
+		self.get_at_mx(mx)
+	}
+
+	fn tile_elevation(&mut self, mx: &MxPos) -> Result<f32, MapError> {
+		self.elevation_at_mx(mx)
+	}
+
+	fn take_dirty(&mut self) -> DirtyCells {
+		let size = self.chunk_size;
+		let mut dirty = DirtyCells::default();
+
+		for (pos, cached) in self.cache.iter_mut() {
+			if let Some(map) = &mut cached.map {
+				let origin = pos.origin(size);
+				let chunk = map.take_dirty();
+				dirty.all |= chunk.all;
+				dirty.cells.extend(chunk.cells.into_iter().map(|x| &origin + x));
+			}
+		}
+		dirty
+	}
+}
+
+fn manifest_path(dir: &str) -> String {
+	Path::new(dir).join(MANIFEST_FILE).to_string_lossy().to_string()
+}
+
+// NOTE: even sizes keep the odd-row hex offset identical inside every chunk
+fn check_chunk_size(chunk_size: u16) -> Result<(), MapError> {
+	if chunk_size == 0 || !chunk_size.is_multiple_of(2) || chunk_size > i16::MAX as u16 {
+		return Err(MapError::out_of_bounds("chunk_size", &chunk_size.to_string(), "an even number below 32768"));
+	}
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// colours that survive being stored as bytes
+	fn blue() -> Color {
+		Color::from_rgba(0, 120, 240, 255)
+	}
+
+	fn red() -> Color {
+		Color::from_rgba(230, 40, 55, 255)
+	}
+
+	fn temp_world(name: &str) -> String {
+		let dir = std::env::temp_dir().join(format!("perspective_{}_{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		dir.to_string_lossy().to_string()
+	}
+
+	#[test]
+	fn only_written_chunks_are_saved() {
+		let dir = temp_world("chunks_saved");
+		let mut world = ChunkedMap::create(&dir, 8).unwrap();
+		assert!(ChunkedMap::exists(&dir));
+
+		let (read, written) = (ChunkPos::new(0, 0), ChunkPos::new(1, -1));
+		world.chunk_mut(&read).unwrap();
+		world.set_color_at_mx(&MxPos::new(9, -3), &blue()).unwrap();
+		assert!(!world.is_dirty(&read));
+		assert!(world.is_dirty(&written));
+
+		world.flush().unwrap();
+		assert!(!world.is_dirty(&written));
+		assert!(!Path::new(&world.chunk_path(&read)).exists());
+		assert!(Path::new(&world.chunk_path(&written)).exists());
+
+		let mut reopened = ChunkedMap::open(&dir).unwrap();
+		assert_eq!(reopened.get_at_mx(&MxPos::new(9, -3)).unwrap(), Some(blue()));
+		assert_eq!(reopened.get_at_mx(&MxPos::new(1, 1)).unwrap(), None);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn evicted_chunks_are_saved_and_read_back() {
+		let dir = temp_world("chunks_evicted");
+		let mut world = ChunkedMap::create(&dir, 4).unwrap();
+		world.set_capacity(2).unwrap();
+
+		for hor in 0..5 {
+			world.set_color_at_mx(&MxPos::new(hor * 4, 0), &red()).unwrap();
+		}
+		assert_eq!(world.loaded_chunks(), 2);
+		assert!(!world.is_loaded(&ChunkPos::new(0, 0)));
+		assert!(Path::new(&world.chunk_path(&ChunkPos::new(0, 0))).exists());
+
+		// reading an evicted chunk back does not make it dirty
+		assert_eq!(world.get_at_mx(&MxPos::new(0, 0)).unwrap(), Some(red()));
+		assert!(!world.is_dirty(&ChunkPos::new(0, 0)));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn unreadable_chunks_are_errors() {
+		let dir = temp_world("chunks_broken");
+		let mut world = ChunkedMap::create(&dir, 4).unwrap();
+		fs::write(world.chunk_path(&ChunkPos::new(0, 0)), b"PMAP").unwrap();
+
+		assert!(world.get_at_mx(&MxPos::new(1, 1)).is_err());
+		assert!(!world.is_loaded(&ChunkPos::new(0, 0)));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn bad_chunk_sizes_are_rejected() {
+		for chunk_size in [0, 7] {
+			let dir = temp_world(&format!("chunks_size_{}", chunk_size));
+			assert!(matches!(ChunkedMap::create(&dir, chunk_size), Err(MapError::OutOfBounds(_))));
+
+			// a hand edited manifest is checked the same way
+			fs::create_dir_all(&dir).unwrap();
+			fs::write(manifest_path(&dir), format!("chunk_size = {}\n", chunk_size)).unwrap();
+			assert!(ChunkedMap::exists(&dir));
+			assert!(matches!(ChunkedMap::open(&dir), Err(MapError::OutOfBounds(_))));
+			fs::remove_dir_all(&dir).unwrap();
+		}
+	}
+}
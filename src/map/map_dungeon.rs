@@ -0,0 +1,408 @@
+use std::{fs};
+use std::collections::VecDeque;
+use serde::*;
+use crate::position::*;
+use super::*;
+
+
+pub const ENTRANCE_TAG: &str = "entrance";
+pub const EXIT_TAG: &str = "exit";
+pub const TREASURE_TAG: &str = "treasure";
+
+
+fn default_floor() -> TerrainType {
+	TerrainType::new("floor", [150, 140, 120, 255])
+}
+
+fn default_wall() -> TerrainType {
+	TerrainType { movement_cost: 0.0, opacity: 1.0, ..TerrainType::new("wall", [60, 55, 50, 255]) }
+}
+
+
+// hex shaped rooms joined by corridors, every room is connected to the room closest to it
+// among the rooms placed before, so all of them can be reached from the entrance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DungeonConfig {
+	pub seed: u64,
+	pub rooms: usize,
+	pub room_radius: [i32; 2],
+	pub treasures: usize,
+	pub floor: TerrainType,
+	pub wall: TerrainType,
+}
+
+impl Default for DungeonConfig {
+	fn default() -> Self {
+		DungeonConfig {
+			seed: 0,
+			rooms: 8,
+			room_radius: [1, 3],
+			treasures: 3,
+			floor: default_floor(),
+			wall: default_wall(),
+		}
+	}
+}
+
+impl DungeonConfig {
+	pub fn read_from_file(file_path: &str) -> Result<DungeonConfig, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))
+	}
+}
+
+
+// cellular automaton caves, cells with at least `birth` walls around them become walls and
+// cells with at most `death` become floor, pockets smaller than `min_region` are filled in
+// and the rest is joined to the largest cave by tunnels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaveConfig {
+	pub seed: u64,
+	// chance for a cell to start as a wall
+	pub fill: f32,
+	pub iterations: usize,
+	pub birth: usize,
+	pub death: usize,
+	pub min_region: usize,
+	pub treasures: usize,
+	pub floor: TerrainType,
+	pub wall: TerrainType,
+}
+
+impl Default for CaveConfig {
+	fn default() -> Self {
+		CaveConfig {
+			seed: 0,
+			fill: 0.45,
+			iterations: 4,
+			birth: 4,
+			death: 2,
+			min_region: 6,
+			treasures: 3,
+			floor: default_floor(),
+			wall: default_wall(),
+		}
+	}
+}
+
+impl CaveConfig {
+	pub fn read_from_file(file_path: &str) -> Result<CaveConfig, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))
+	}
+}
+
+
+// the tagged cells of a generated layout, rooms holds the centre of every dungeon room
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutReport {
+	pub entrance: MxPos,
+	pub exit: MxPos,
+	pub treasures: Vec<MxPos>,
+	pub rooms: Vec<MxPos>,
+}
+
+
+struct Room {
+	center: MxPos,
+	radius: i32,
+}
+
+
+// both generators replace the palette with floor and wall, drop all cell data and like other
+// generators bypass and clear the history
+impl Map {
+	pub fn generate_dungeon(&mut self, config: &DungeonConfig) -> Result<LayoutReport, MapError> {
+		let mut rng = MapRng::new(config.seed);
+		let mut floor = vec![false; self.matrix.len()];
+		let (min_radius, max_radius) = (config.room_radius[0].max(0), config.room_radius[1].max(config.room_radius[0]).max(0));
+
+		// rooms keep a wall between them and the border
+		let mut rooms: Vec<Room> = Vec::new();
+		for _ in 0..config.rooms * 20 {
+			if rooms.len() >= config.rooms { break; }
+
+			let radius = min_radius + rng.below((max_radius - min_radius + 1) as usize) as i32;
+			let margin = radius + 1;
+			let (width, height) = (self.width as i32 - 2 * margin, self.height as i32 - 2 * margin);
+			if width <= 0 || height <= 0 { continue; }
+
+			let center = MxPos::new(margin + rng.below(width as usize) as i32, margin + rng.below(height as usize) as i32);
+			if rooms.iter().any(|x| x.center.hex_distance(&center) <= x.radius + radius + 1) { continue; }
+			rooms.push(Room { center, radius });
+		}
+		if rooms.is_empty() {
+			return Err(MapError::InvalidConfig(format!("a {}x{} map has no space for rooms", self.width, self.height)));
+		}
+
+		for room in &rooms {
+			self.carve(&mut floor, &room.center.range(room.radius));
+		}
+		for (idx, room) in rooms.iter().enumerate().skip(1) {
+			let nearest = rooms[..idx].iter()
+				.min_by_key(|x| x.center.hex_distance(&room.center))
+				.unwrap();
+			self.carve(&mut floor, &room.center.line_to(&nearest.center));
+		}
+
+		let entrance = rooms[0].center.clone();
+		let distances = self.floor_distances(&floor, &entrance);
+		let exit = rooms.iter()
+			.map(|x| &x.center)
+			.max_by_key(|x| distances[self.mx_index(x).unwrap()])
+			.unwrap()
+			.clone();
+
+		// treasure goes to the other rooms first, a random cell away from the centre when possible,
+		// never onto the entrance or the exit
+		let mut candidates: Vec<&Room> = rooms.iter().filter(|x| x.center != entrance && x.center != exit).collect();
+		if candidates.is_empty() {
+			candidates = rooms.iter().collect();
+		}
+		rng.shuffle(&mut candidates);
+		let mut treasures = Vec::new();
+		for room in candidates.iter().cycle().take(config.treasures) {
+			let cells: Vec<MxPos> = room.center.range(room.radius).into_iter()
+				.filter(|x| (*x != room.center || room.radius == 0) && *x != entrance && *x != exit && !treasures.contains(x))
+				.collect();
+			if !cells.is_empty() {
+				treasures.push(cells[rng.below(cells.len())].clone());
+			}
+		}
+
+		let report = LayoutReport { entrance, exit, treasures, rooms: rooms.into_iter().map(|x| x.center).collect() };
+		self.write_layout(&floor, &config.floor, &config.wall, &report)?;
+		Ok(report)
+	}
+
+	pub fn generate_caves(&mut self, config: &CaveConfig) -> Result<LayoutReport, MapError> {
+		let mut rng = MapRng::new(config.seed);
+		let positions: Vec<MxPos> = self.positions().collect();
+		let mut floor: Vec<bool> = positions.iter().map(|_| !rng.chance(config.fill)).collect();
+
+		for _ in 0..config.iterations {
+			floor = positions.iter().enumerate().map(|(idx, mx)| {
+				let walls = (0..6)
+					.filter(|x| match self.neighbour_at_mx(mx, *x) {
+						Some(next) => !floor[self.mx_index(&next).unwrap()],
+						None => true,
+					})
+					.count();
+				match walls {
+					walls if walls >= config.birth => false,
+					walls if walls <= config.death => true,
+					_ => floor[idx],
+				}
+			}).collect();
+		}
+
+		// the border stays solid unless the map wraps around it
+		for (idx, mx) in positions.iter().enumerate() {
+			if (0..6).any(|x| self.neighbour_at_mx(mx, x).is_none()) {
+				floor[idx] = false;
+			}
+		}
+
+		let mut regions: Vec<Vec<MxPos>> = self.label_regions(|map, mx| floor[map.mx_index(mx).unwrap()])
+			.regions()
+			.iter()
+			.map(|x| x.cells.clone())
+			.collect();
+		regions.sort_by_key(|x| std::cmp::Reverse(x.len()));
+		if regions.is_empty() {
+			return Err(MapError::InvalidConfig("the caves have no floor left".to_string()));
+		}
+
+		// the largest cave stays even when it is small, the others are tunnelled to it
+		let mut connected = regions.remove(0);
+		for region in regions {
+			if region.len() < config.min_region {
+				region.iter().for_each(|mx| floor[self.mx_index(mx).unwrap()] = false);
+				continue;
+			}
+
+			let (from, to) = region.iter()
+				.flat_map(|a| connected.iter().map(move |b| (a, b)))
+				.min_by_key(|(a, b)| a.hex_distance(b))
+				.unwrap();
+			self.carve(&mut floor, &from.line_to(to));
+			connected.extend(region);
+		}
+
+		let entrance = connected[rng.below(connected.len())].clone();
+		let distances = self.floor_distances(&floor, &entrance);
+		let exit = self.mx_of((0..floor.len()).max_by_key(|x| (distances[*x], std::cmp::Reverse(*x))).unwrap());
+
+		// treasure hides in nooks, cells with the most walls around them
+		let mut nooks: Vec<(usize, MxPos)> = positions.iter()
+			.filter(|x| floor[self.mx_index(x).unwrap()] && **x != entrance && **x != exit)
+			.map(|mx| ((0..6).filter(|x| self.neighbour_at_mx(mx, *x).is_none_or(|x| !floor[self.mx_index(&x).unwrap()])).count(), mx.clone()))
+			.collect();
+		rng.shuffle(&mut nooks);
+		nooks.sort_by_key(|x| std::cmp::Reverse(x.0));
+		let treasures = nooks.into_iter().take(config.treasures).map(|x| x.1).collect();
+
+		let report = LayoutReport { entrance, exit, treasures, rooms: Vec::new() };
+		self.write_layout(&floor, &config.floor, &config.wall, &report)?;
+		Ok(report)
+	}
+
+	fn carve(&self, floor: &mut [bool], cells: &[MxPos]) {
+		for mx in cells {
+			if let Some(idx) = self.write_index(mx) {
+				floor[idx] = true;
+			}
+		}
+	}
+
+	// steps from `from` to every floor cell, unreachable cells and walls are None
+	fn floor_distances(&self, floor: &[bool], from: &MxPos) -> Vec<Option<u32>> {
+		let mut distances = vec![None; floor.len()];
+		let start = match self.mx_index(from) {
+			Some(start) => start,
+			None => return distances,
+		};
+
+		distances[start] = Some(0);
+		let mut queue = VecDeque::from([(from.clone(), 0)]);
+		while let Some((mx, steps)) = queue.pop_front() {
+			for next in self.neighbours_at_mx(&mx) {
+				let idx = self.mx_index(&next).unwrap();
+				if floor[idx] && distances[idx].is_none() {
+					distances[idx] = Some(steps + 1);
+					queue.push_back((next, steps + 1));
+				}
+			}
+		}
+		distances
+	}
+
+	fn write_layout(&mut self, floor: &[bool], floor_terrain: &TerrainType, wall_terrain: &TerrainType, report: &LayoutReport) -> Result<(), MapError> {
+		let mut palette = Palette::new();
+		let floor_idx = palette.define(floor_terrain.clone())?;
+		let wall_idx = palette.define(wall_terrain.clone())?;
+
+		let mut terrain = vec![wall_idx; floor.len()];
+		for (idx, is_floor) in floor.iter().enumerate() {
+			let tile = if *is_floor { floor_terrain } else { wall_terrain };
+			self.matrix[idx].color = tile.color;
+			if *is_floor { terrain[idx] = floor_idx; }
+		}
+
+		self.palette = palette;
+		self.replace_layer(TERRAIN_LAYER, LayerData::U16(terrain));
+		self.cell_data.clear();
+		self.tag_cell(&report.entrance, ENTRANCE_TAG)?;
+		self.tag_cell(&report.exit, EXIT_TAG)?;
+		for mx in &report.treasures {
+			self.tag_cell(mx, TREASURE_TAG)?;
+		}
+		self.clear_history();
+		self.mark_all_dirty();
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn is_floor(map: &Map, mx: &MxPos) -> bool {
+		let floor = map.palette.index_of("floor").unwrap();
+		map.get_layer_at_mx(TERRAIN_LAYER, mx) == Some(LayerValue::U16(floor))
+	}
+
+	// the guarantees every layout has to keep, whatever the generator
+	fn assert_layout(map: &Map, report: &LayoutReport, treasures: usize) {
+		let regions = map.label_regions(is_floor);
+		assert!(regions.label_at(&report.entrance).is_some(), "the entrance is not on the floor");
+		for mx in report.rooms.iter().chain([&report.exit]).chain(&report.treasures) {
+			assert!(regions.connected(&report.entrance, mx), "{:?} is cut off", mx);
+		}
+		assert_eq!(regions.len(), 1, "the floor is split up");
+
+		assert_eq!(map.cells_tagged(ENTRANCE_TAG), vec![report.entrance.clone()]);
+		assert_eq!(map.cells_tagged(EXIT_TAG), vec![report.exit.clone()]);
+		assert_ne!(report.entrance, report.exit);
+
+		let mut tagged = map.cells_tagged(TREASURE_TAG);
+		let mut expected = report.treasures.clone();
+		tagged.sort_by_key(|x| (x.ver, x.hor));
+		expected.sort_by_key(|x| (x.ver, x.hor));
+		assert_eq!(tagged, expected);
+		assert_eq!(tagged.len(), treasures);
+		for mx in &tagged {
+			assert!(is_floor(map, mx));
+			assert!(*mx != report.entrance && *mx != report.exit);
+		}
+
+		if map.edge_mode().is_void() {
+			for mx in map.positions() {
+				if mx.hor == 0 || mx.ver == 0 || mx.hor == map.width as i32 - 1 || mx.ver == map.height as i32 - 1 {
+					assert!(!is_floor(map, &mx), "{:?} is floor on the border", mx);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn dungeon_rooms_are_connected_and_tagged() {
+		for (width, height) in [(30, 20), (60, 40), (17, 33)] {
+		for seed in 0..12 {
+			let mut map = Map::filled(width, height, &GRAY).unwrap();
+			let config = DungeonConfig { seed, ..Default::default() };
+			let report = map.generate_dungeon(&config).unwrap();
+			assert!(report.rooms.len() >= 2, "{}x{} seed {}", width, height, seed);
+			assert_layout(&map, &report, config.treasures);
+		}}
+	}
+
+	#[test]
+	fn single_cell_rooms_keep_treasure_off_the_entrance_and_exit() {
+		for seed in 0..12 {
+			let mut map = Map::filled(20, 16, &GRAY).unwrap();
+			let config = DungeonConfig { seed, rooms: 2, room_radius: [0, 0], treasures: 2, ..Default::default() };
+			let report = map.generate_dungeon(&config).unwrap();
+			assert!(report.treasures.is_empty());
+			assert_layout(&map, &report, 0);
+		}
+	}
+
+	#[test]
+	fn dungeons_need_space_for_a_room() {
+		let mut map = Map::filled(3, 3, &GRAY).unwrap();
+		assert!(matches!(map.generate_dungeon(&DungeonConfig::default()), Err(MapError::InvalidConfig(_))));
+	}
+
+	#[test]
+	fn caves_are_connected_and_tagged() {
+		for (width, height, edge) in [(40, 30, EdgeMode::Void), (60, 40, EdgeMode::WrapHorizontal), (25, 36, EdgeMode::Torus)] {
+		for seed in 0..12 {
+			let mut map = Map::filled(width, height, &GRAY).unwrap();
+			map.set_edge_mode(edge);
+			let config = CaveConfig { seed, ..Default::default() };
+			let report = map.generate_caves(&config).unwrap();
+			assert!(report.rooms.is_empty());
+			assert_layout(&map, &report, config.treasures);
+		}}
+	}
+
+	#[test]
+	fn same_seed_same_layout() {
+		let generate = || {
+			let mut map = Map::filled(40, 30, &GRAY).unwrap();
+			let report = map.generate_dungeon(&DungeonConfig { seed: 7, ..Default::default() }).unwrap();
+			(map.to_bytes().unwrap(), report)
+		};
+		assert_eq!(generate(), generate());
+	}
+}
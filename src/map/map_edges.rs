@@ -0,0 +1,223 @@
+use serde::*;
+use crate::position::*;
+use super::*;
+
+
+// what lies beyond the border of a map
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeMode {
+	// nothing, positions outside of the map have no cell
+	#[default]
+	Void,
+	// the nearest border cell repeats forever
+	Clamp,
+	// the left and right border connect, like a cylinder or a globe
+	WrapHorizontal,
+	// both borders connect, like a donut
+	// NOTE: odd rows are shifted, so with an odd height every trip over the top or bottom border
+	// also moves half a cell sideways, the grid stays seamless but is twisted
+	Torus,
+}
+
+impl EdgeMode {
+	pub fn is_void(&self) -> bool {
+		*self == EdgeMode::Void
+	}
+
+	fn wraps_hor(&self) -> bool {
+		matches!(self, EdgeMode::WrapHorizontal | EdgeMode::Torus)
+	}
+
+	fn wraps_ver(&self) -> bool {
+		matches!(self, EdgeMode::Torus)
+	}
+}
+
+
+impl Map {
+	pub fn edge_mode(&self) -> EdgeMode {
+		self.header.edge
+	}
+
+	// everything outside of the map looks different afterwards, so all cells are redrawn
+	pub fn set_edge_mode(&mut self, mode: EdgeMode) {
+		if self.header.edge == mode { return; }
+		self.header.edge = mode;
+		self.mark_all_dirty();
+	}
+
+	// the cell a position shows under the edge mode, None when it lies in the void
+	pub fn resolve_mx(&self, mx: &MxPos) -> Option<MxPos> {
+		if self.width <= 0 || self.height <= 0 { return None; }
+
+		let mx = match self.header.edge {
+			EdgeMode::Clamp => MxPos::new(
+				mx.hor.clamp(0, self.width as i32 - 1),
+				mx.ver.clamp(0, self.height as i32 - 1),
+			),
+			_ => self.wrap_mx(mx),
+		};
+		self.mx_index(&mx).map(|_| mx)
+	}
+
+	// only applies wrapping, so brushes can paint across a seam but never smear a clamped border
+	pub fn wrap_mx(&self, mx: &MxPos) -> MxPos {
+		let mode = self.header.edge;
+		let mut mx = mx.clone();
+		// rows wrap as a move on the hex grid, so odd heights keep neighbours on both sides alike
+		if mode.wraps_ver() && self.height > 0 {
+			let height = self.height as i32;
+			let turns = mx.ver.div_euclid(height);
+			if turns != 0 {
+				mx = MxPos::from(CubePos::from(&mx) - CubePos::new(-turns * ((height + 1) / 2), turns * height));
+			}
+		}
+		if mode.wraps_hor() && self.width > 0 {
+			mx.hor = mx.hor.rem_euclid(self.width as i32);
+		}
+		mx
+	}
+
+	// the distinct cells next to a cell, across seams when the map wraps
+	// NOTE: clamped borders have no extra neighbours, they would only repeat the cell itself
+	pub fn neighbours_at_mx(&self, mx: &MxPos) -> Vec<MxPos> {
+		let mut cells: Vec<MxPos> = Vec::with_capacity(6);
+		for next in mx.neighbours() {
+			let next = self.wrap_mx(&next);
+			if self.mx_index(&next).is_some() && next != *mx && !cells.contains(&next) {
+				cells.push(next);
+			}
+		}
+		cells
+	}
+
+	// the cell in one of the CUBE_DIRECTIONS, across a seam when the map wraps
+	pub fn neighbour_at_mx(&self, mx: &MxPos, direction: usize) -> Option<MxPos> {
+		let cube = CubePos::from(mx) + CUBE_DIRECTIONS[direction % 6];
+		let next = self.wrap_mx(&MxPos::from(cube));
+		self.mx_index(&next).map(|_| next)
+	}
+
+	pub(super) fn read_index(&self, mx: &MxPos) -> Option<usize> {
+		self.mx_index(&self.resolve_mx(mx)?)
+	}
+
+	pub(super) fn write_index(&self, mx: &MxPos) -> Option<usize> {
+		self.mx_index(&self.wrap_mx(mx))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn map(width: usize, height: usize, edge: EdgeMode) -> Map {
+		let mut map = Map::filled(width, height, &GRAY).unwrap();
+		map.set_edge_mode(edge);
+		map
+	}
+
+	// every step has a way back, so fills and regions look the same from both sides
+	fn assert_symmetric(map: &Map) {
+		for mx in map.positions() {
+			for direction in 0..6 {
+				if let Some(next) = map.neighbour_at_mx(&mx, direction) {
+					assert_eq!(map.neighbour_at_mx(&next, direction + 3), Some(mx.clone()), "{:?} in direction {}", mx, direction);
+					assert!(map.neighbours_at_mx(&next).contains(&mx));
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn void_edges_have_no_neighbours() {
+		let map = map(6, 4, EdgeMode::Void);
+		assert_eq!(map.neighbours_at_mx(&MxPos::new(0, 0)), vec![MxPos::new(1, 0), MxPos::new(0, 1)]);
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(0, 2), 3), None);
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(5, 1), 0), None);
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(5, 1), 5), None);
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(5, 1), 4), Some(MxPos::new(5, 2)));
+		assert_eq!(map.neighbours_at_mx(&MxPos::new(2, 2)).len(), 6);
+		assert_eq!(map.resolve_mx(&MxPos::new(-1, 0)), None);
+		assert_eq!(map.get_at_mx(&MxPos::new(6, 0)), None);
+		assert_symmetric(&map);
+	}
+
+	#[test]
+	fn clamped_edges_repeat_the_border() {
+		let mut map = map(6, 4, EdgeMode::Clamp);
+		let red = Color::from_rgba(200, 30, 30, 255);
+		map.set_at_mx(&MxPos::new(0, 3), &red).unwrap();
+
+		assert_eq!(map.resolve_mx(&MxPos::new(-5, 9)), Some(MxPos::new(0, 3)));
+		assert_eq!(map.resolve_mx(&MxPos::new(8, -2)), Some(MxPos::new(5, 0)));
+		assert_eq!(map.get_at_mx(&MxPos::new(-1, 4)), Some(red));
+
+		// the border is not its own neighbour and writes never land on it
+		assert_eq!(map.neighbours_at_mx(&MxPos::new(0, 0)).len(), 2);
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(0, 2), 3), None);
+		assert!(map.paint(&MxPos::new(-1, 3), &Paint::Color([1, 2, 3, 255])).is_err());
+		assert_symmetric(&map);
+	}
+
+	#[test]
+	fn horizontal_wrapping_joins_left_and_right() {
+		let map = map(6, 4, EdgeMode::WrapHorizontal);
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(0, 2), 3), Some(MxPos::new(5, 2)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(5, 1), 0), Some(MxPos::new(0, 1)));
+		// odd rows lean right, so the south east of the last odd cell is the first cell below
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(5, 1), 5), Some(MxPos::new(0, 2)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(0, 2), 4), Some(MxPos::new(5, 3)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(2, 0), 1), None);
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(2, 3), 5), None);
+		assert_eq!(map.neighbours_at_mx(&MxPos::new(0, 1)).len(), 6);
+		assert_eq!(map.resolve_mx(&MxPos::new(-7, 1)), Some(MxPos::new(5, 1)));
+		assert_symmetric(&map);
+	}
+
+	#[test]
+	fn tori_join_every_border() {
+		let map = map(6, 4, EdgeMode::Torus);
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(2, 0), 2), Some(MxPos::new(1, 3)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(2, 0), 1), Some(MxPos::new(2, 3)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(2, 3), 4), Some(MxPos::new(2, 0)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(5, 3), 5), Some(MxPos::new(0, 0)));
+		assert_eq!(map.resolve_mx(&MxPos::new(8, -5)), Some(MxPos::new(2, 3)));
+		for mx in map.positions() {
+			assert_eq!(map.neighbours_at_mx(&mx).len(), 6);
+		}
+		assert_symmetric(&map);
+	}
+
+	#[test]
+	fn odd_tori_move_sideways_at_the_seam() {
+		let map = map(6, 5, EdgeMode::Torus);
+		// the last row and the first are both even, so going down leans half a cell right
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(2, 4), 4), Some(MxPos::new(2, 0)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(2, 4), 5), Some(MxPos::new(3, 0)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(3, 0), 2), Some(MxPos::new(2, 4)));
+		assert_eq!(map.neighbour_at_mx(&MxPos::new(3, 0), 1), Some(MxPos::new(3, 4)));
+		assert_eq!(map.resolve_mx(&MxPos::new(2, 5)), Some(MxPos::new(3, 0)));
+		for mx in map.positions() {
+			assert_eq!(map.neighbours_at_mx(&mx).len(), 6);
+		}
+		assert_symmetric(&map);
+		assert_symmetric(&self::map(5, 7, EdgeMode::Torus));
+	}
+
+	#[test]
+	fn flood_fill_crosses_seams() {
+		let wall = Color::from_rgba(20, 20, 20, 255);
+		let water = Paint::Color([30, 80, 200, 255]);
+		for (edge, filled) in [(EdgeMode::Void, 18), (EdgeMode::Clamp, 18), (EdgeMode::WrapHorizontal, 36), (EdgeMode::Torus, 36)] {
+			let mut map = map(7, 6, edge);
+			for ver in 0..6 {
+				map.set_at_mx(&MxPos::new(3, ver), &wall).unwrap();
+			}
+			map.clear_history();
+			assert_eq!(map.flood_fill(&MxPos::new(0, 0), &water).unwrap(), filled, "{:?}", edge);
+		}
+	}
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashSet, VecDeque};
+use macroquad::prelude::*;
+use crate::position::*;
+use super::*;
+
+
+// what a brush writes into every cell it touches
+#[derive(Debug, Clone, PartialEq)]
+pub enum Paint {
+	Color([u8; 4]),
+	Terrain(String),
+	Layer(String, LayerValue),
+}
+
+impl From<&Color> for Paint {
+	fn from(col: &Color) -> Paint {
+		Paint::Color(MapValue::from(col).color)
+	}
+}
+
+impl From<Color> for Paint {
+	fn from(col: Color) -> Paint {
+		Paint::from(&col)
+	}
+}
+
+// cells changed since the last time they were taken, `all` is set by map wide changes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DirtyCells {
+	pub all: bool,
+	pub cells: HashSet<MxPos>,
+}
+
+impl DirtyCells {
+	pub fn contains(&self, mx: &MxPos) -> bool {
+		self.all || self.cells.contains(mx)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		!self.all && self.cells.is_empty()
+	}
+
+	pub fn extend(&mut self, other: DirtyCells) {
+		self.all |= other.all;
+		self.cells.extend(other.cells);
+	}
+}
+
+// the value a paint would overwrite, used to find connected regions for flood fills
+#[derive(Debug, Clone, PartialEq)]
+enum PaintTarget {
+	Color([u8; 4]),
+	Layer(String, LayerValue),
+}
+
+
+impl Map {
+	pub fn set_at_mx(&mut self, mx: &MxPos, col: &Color) -> Result<(), MapError> {
+		self.paint(mx, &Paint::from(col)).map(|_| ())
+	}
+
+	// returns whether the cell actually changed
+	pub fn paint(&mut self, mx: &MxPos, paint: &Paint) -> Result<bool, MapError> {
+		self.transaction(|map| map.paint_cell(mx, paint))
+	}
+
+	// cells outside of the map are skipped unless the map wraps, all brushes return the number of changed cells
+	// and are recorded as a single undo step
+	pub fn paint_cells(&mut self, cells: &[MxPos], paint: &Paint) -> Result<usize, MapError> {
+		self.transaction(|map| {
+			let mut changed = 0;
+			for mx in cells {
+				if map.write_index(mx).is_some() && map.paint_cell(mx, paint)? {
+					changed += 1;
+				}
+			}
+			Ok(changed)
+		})
+	}
+
+	pub(super) fn paint_cell(&mut self, mx: &MxPos, paint: &Paint) -> Result<bool, MapError> {
+		let idx = self.write_index(mx)
+			.ok_or_else(|| MapError::out_of_bounds("mx", &format!("{:?}", mx), &format!("{}x{}", self.width, self.height)))?;
+
+		match paint {
+			Paint::Color(color) => Ok(self.write_color(idx, *color)),
+			Paint::Terrain(name) => {
+				let terrain = self.palette.index_of(name)
+					.ok_or_else(|| MapError::UnknownTerrain(name.to_string()))?;
+
+				if self.layer(TERRAIN_LAYER).is_none() {
+					self.add_layer(TERRAIN_LAYER, LayerKind::U16)?;
+				}
+				self.write_layer(idx, TERRAIN_LAYER, LayerValue::U16(terrain))
+			},
+			Paint::Layer(name, value) => self.write_layer(idx, name, *value),
+		}
+	}
+
+	pub fn fill_rect(&mut self, from: &MxPos, to: &MxPos, paint: &Paint) -> Result<usize, MapError> {
+		let mut cells = Vec::new();
+		for ver in from.ver.min(to.ver)..=from.ver.max(to.ver) {
+		for hor in from.hor.min(to.hor)..=from.hor.max(to.hor) {
+			cells.push(MxPos::new(hor, ver));
+		}}
+		self.paint_cells(&cells, paint)
+	}
+
+	pub fn fill_hex_range(&mut self, center: &MxPos, radius: i32, paint: &Paint) -> Result<usize, MapError> {
+		self.paint_cells(&center.range(radius), paint)
+	}
+
+	pub fn draw_line(&mut self, from: &MxPos, to: &MxPos, paint: &Paint) -> Result<usize, MapError> {
+		self.paint_cells(&from.line_to(to), paint)
+	}
+
+	pub fn draw_circle(&mut self, center: &MxPos, radius: i32, paint: &Paint) -> Result<usize, MapError> {
+		self.paint_cells(&center.ring(radius), paint)
+	}
+
+	// paints the connected region of cells that hold the same value the paint would replace
+	pub fn flood_fill(&mut self, start: &MxPos, paint: &Paint) -> Result<usize, MapError> {
+		let target = match self.paint_target(start, paint)? {
+			Some(target) => target,
+			None => return Ok(0),
+		};
+
+		let mut seen = HashSet::new();
+		let mut queue = VecDeque::new();
+		let mut region = Vec::new();
+		let start = self.wrap_mx(start);
+		seen.insert(start.clone());
+		queue.push_back(start);
+
+		while let Some(mx) = queue.pop_front() {
+			if self.paint_target(&mx, paint)?.as_ref() != Some(&target) { continue; }
+			region.push(mx.clone());
+
+			for next in self.neighbours_at_mx(&mx) {
+				if seen.insert(next.clone()) {
+					queue.push_back(next);
+				}
+			}
+		}
+		self.paint_cells(&region, paint)
+	}
+
+	pub fn dirty_cells(&self) -> &DirtyCells {
+		&self.dirty
+	}
+
+	pub fn take_dirty(&mut self) -> DirtyCells {
+		std::mem::take(&mut self.dirty)
+	}
+
+	pub fn mark_all_dirty(&mut self) {
+		self.dirty.all = true;
+		self.revision += 1;
+	}
+
+	// counts up on every change, so savers can tell whether anything changed since they last looked
+	pub fn revision(&self) -> u64 {
+		self.revision
+	}
+
+	fn paint_target(&self, mx: &MxPos, paint: &Paint) -> Result<Option<PaintTarget>, MapError> {
+		let idx = match self.write_index(mx) {
+			Some(idx) => idx,
+			None => return Ok(None),
+		};
+
+		let name = match paint {
+			Paint::Color(_) => return Ok(Some(PaintTarget::Color(self.matrix[idx].color))),
+			Paint::Terrain(_) => TERRAIN_LAYER,
+			Paint::Layer(name, _) => name,
+		};
+
+		// NOTE: a missing terrain layer means every cell is still on the first terrain
+		match self.layer(name) {
+			Some(layer) => Ok(layer.data.get(idx).map(|x| PaintTarget::Layer(name.to_string(), x))),
+			None if name == TERRAIN_LAYER => Ok(Some(PaintTarget::Layer(name.to_string(), LayerValue::U16(0)))),
+			None => Err(MapError::UnknownLayer(name.to_string())),
+		}
+	}
+
+	fn write_color(&mut self, idx: usize, color: [u8; 4]) -> bool {
+		let old = self.matrix[idx].color;
+		if old == color { return false; }
+
+		self.record(MapChange::Color { idx, old, new: color });
+		self.matrix[idx].color = color;
+		self.mark_dirty(idx);
+		true
+	}
+
+	fn write_layer(&mut self, idx: usize, name: &str, value: LayerValue) -> Result<bool, MapError> {
+		let layer = self.layer_mut(name)
+			.ok_or_else(|| MapError::UnknownLayer(name.to_string()))?;
+
+		let old = match layer.data.get(idx) {
+			Some(old) if old == value => return Ok(false),
+			Some(old) => old,
+			None => return Ok(false),
+		};
+
+		match layer.data.set(idx, value) {
+			true => {
+				self.record(MapChange::Layer { idx, layer: name.to_string(), old, new: value });
+				self.mark_dirty(idx);
+				Ok(true)
+			},
+			false => Err(MapError::LayerKindMismatch {
+				layer: name.to_string(),
+				expected: layer.kind(),
+				found: value.kind(),
+			}),
+		}
+	}
+
+	pub(super) fn mark_dirty(&mut self, idx: usize) {
+		let width = self.width as usize;
+		self.dirty.cells.insert(MxPos::new((idx % width) as i32, (idx / width) as i32));
+		self.revision += 1;
+	}
+}
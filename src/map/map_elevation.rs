@@ -0,0 +1,28 @@
+use crate::position::*;
+use super::*;
+
+
+pub const ELEVATION_LAYER: &str = "elevation";
+
+
+impl Map {
+	// cells without an elevation layer are flat at height 0.0
+	pub fn elevation_at_mx(&self, mx: &MxPos) -> f32 {
+		match self.get_layer_at_mx(ELEVATION_LAYER, mx) {
+			Some(LayerValue::F32(height)) => height,
+			_ => 0.0,
+		}
+	}
+
+	pub fn set_elevation_at_mx(&mut self, mx: &MxPos, height: f32) -> Result<(), MapError> {
+		if self.layer(ELEVATION_LAYER).is_none() {
+			self.add_layer(ELEVATION_LAYER, LayerKind::F32)?;
+		}
+		self.set_layer_at_mx(ELEVATION_LAYER, mx, LayerValue::F32(height))
+	}
+
+	// world position on top of the cell below the tile position
+	pub fn real_pos_at(&self, tile: &TilePos) -> RealPos {
+		RealPos::from(tile).with_height(self.elevation_at_mx(&MxPos::from(tile)))
+	}
+}
@@ -0,0 +1,202 @@
+use std::{fs};
+use serde::*;
+use noise::*;
+use crate::position::*;
+use super::*;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseFunction {
+	Perlin,
+	OpenSimplex,
+	Worley,
+	Fbm,
+	RidgedMulti,
+}
+
+// one noise field, octaves, lacunarity and persistence only matter to fbm and ridged multi
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseSettings {
+	pub seed: u32,
+	#[serde(rename = "noise")]
+	pub function: NoiseFunction,
+	pub octaves: usize,
+	// features per cell, 0.1 gives blobs about ten cells across
+	pub frequency: f64,
+	pub lacunarity: f64,
+	pub persistence: f64,
+}
+
+impl Default for NoiseSettings {
+	fn default() -> Self {
+		NoiseSettings {
+			seed: 0,
+			function: NoiseFunction::Perlin,
+			octaves: 6,
+			frequency: 1.0 / 3.0,
+			lacunarity: 2.0,
+			persistence: 0.5,
+		}
+	}
+}
+
+impl NoiseSettings {
+	pub fn with_seed(&self, seed: u32) -> Self {
+		NoiseSettings { seed, ..self.clone() }
+	}
+
+	// NOTE: frequency is applied to the sample position, so every function is scaled the same way
+	pub fn build(&self) -> Box<dyn NoiseFn<f64, 2>> {
+		match self.function {
+			NoiseFunction::Perlin => Box::new(Perlin::new(self.seed)),
+			NoiseFunction::OpenSimplex => Box::new(OpenSimplex::new(self.seed)),
+			NoiseFunction::Worley => Box::new(Worley::new(self.seed)),
+			NoiseFunction::Fbm => Box::new(Fbm::<Perlin>::new(self.seed)
+				.set_octaves(self.octaves)
+				.set_lacunarity(self.lacunarity)
+				.set_persistence(self.persistence)),
+			// NOTE: RidgedMulti::new keeps the default seed for its octaves, set_seed rebuilds them
+			NoiseFunction::RidgedMulti => Box::new(RidgedMulti::<Perlin>::default()
+				.set_seed(self.seed)
+				.set_octaves(self.octaves)
+				.set_lacunarity(self.lacunarity)
+				.set_persistence(self.persistence)),
+		}
+	}
+
+	// samples at the centre of the hex, so features are not stretched by the row offset,
+	// the result is roughly spread over 0.0 to 1.0
+	pub fn sample(&self, noise: &dyn NoiseFn<f64, 2>, mx: &MxPos) -> f32 {
+		let x = mx.hor as f64 + if mx.ver % 2 != 0 { 0.5 } else { 0.0 };
+		let y = mx.ver as f64 * 0.75f64.sqrt();
+		let value = noise.get([x * self.frequency, y * self.frequency]);
+		((value as f32 + 1.0) * 0.5).clamp(0.0, 1.0)
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RampStop {
+	pub at: f32,
+	pub color: [u8; 4],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneratorConfig {
+	#[serde(flatten)]
+	pub noise: NoiseSettings,
+	// colours for noise values from 0.0 to 1.0, blended between stops
+	pub ramp: Vec<RampStop>,
+}
+
+impl Default for GeneratorConfig {
+	fn default() -> Self {
+		GeneratorConfig {
+			noise: NoiseSettings::default(),
+			ramp: vec![
+				RampStop { at: 0.0, color: [64, 166, 128, 255] },
+				RampStop { at: 1.0, color: [64, 217, 128, 255] },
+			],
+		}
+	}
+}
+
+impl GeneratorConfig {
+	pub fn read_from_file(file_path: &str) -> Result<GeneratorConfig, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))
+	}
+
+	pub fn with_seed(&self, seed: u32) -> Self {
+		GeneratorConfig { noise: self.noise.with_seed(seed), ..self.clone() }
+	}
+
+	// stops may be listed in any order, without stops the value becomes a shade of gray
+	pub fn ramp_color(&self, value: f32) -> [u8; 4] {
+		let mut stops: Vec<&RampStop> = self.ramp.iter().collect();
+		stops.sort_by(|a, b| a.at.total_cmp(&b.at));
+
+		let upper = match stops.iter().position(|x| x.at >= value) {
+			Some(upper) => upper,
+			None => return stops.last().map(|x| x.color).unwrap_or_else(|| gray(value)),
+		};
+		if upper == 0 {
+			return stops[0].color;
+		}
+
+		let (from, to) = (stops[upper - 1], stops[upper]);
+		let t = (value - from.at) / (to.at - from.at);
+		let mut color = [0; 4];
+		for (channel, (a, b)) in color.iter_mut().zip(from.color.iter().zip(to.color)) {
+			*channel = (*a as f32 + (b as f32 - *a as f32) * t).round() as u8;
+		}
+		color
+	}
+}
+
+fn gray(value: f32) -> [u8; 4] {
+	let shade = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+	[shade, shade, shade, 255]
+}
+
+
+impl Map {
+	// overwrites every cell colour, bypassing the history
+	pub fn generate(&mut self, config: &GeneratorConfig) {
+		let noise = config.noise.build();
+		let width = self.width.max(0) as i32;
+
+		for (idx, value) in self.matrix.iter_mut().enumerate() {
+			let mx = MxPos::new(idx as i32 % width, idx as i32 / width);
+			value.color = config.ramp_color(config.noise.sample(noise.as_ref(), &mx));
+		}
+		self.mark_all_dirty();
+	}
+}
+
+
+// a small splitmix64 generator, the same seed gives the same numbers on every platform
+#[derive(Debug, Clone)]
+pub struct MapRng {
+	state: u64,
+}
+
+impl MapRng {
+	pub fn new(seed: u64) -> Self {
+		MapRng { state: seed }
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut x = self.state;
+		x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		x ^ (x >> 31)
+	}
+
+	// 0 up to but not including `n`, 0 when `n` is 0
+	pub fn below(&mut self, n: usize) -> usize {
+		((self.next_u64() as u128 * n as u128) >> 64) as usize
+	}
+
+	// 0.0 up to but not including 1.0
+	pub fn next_f32(&mut self) -> f32 {
+		(self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+	}
+
+	pub fn chance(&mut self, probability: f32) -> bool {
+		self.next_f32() < probability
+	}
+
+	pub fn shuffle<T>(&mut self, items: &mut [T]) {
+		for idx in (1..items.len()).rev() {
+			items.swap(idx, self.below(idx + 1));
+		}
+	}
+}
@@ -0,0 +1,195 @@
+use std::{fs};
+use std::path::Path;
+use serde::*;
+use toml_edit::Document;
+use super::*;
+use super::map_biomes::BiomeConfig;
+use super::map_dungeon::{CaveConfig, DungeonConfig};
+use super::map_hydrology::HydrologyConfig;
+use super::map_wfc::{WfcOptions, WfcRules};
+
+
+// one generator run of a golden case, configs are written inline and missing fields use the
+// defaults, wfc rules are read from a file next to the suite
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "generator", rename_all = "snake_case")]
+pub enum GoldenStep {
+	Noise(GeneratorConfig),
+	Biomes(BiomeConfig),
+	Hydrology(HydrologyConfig),
+	Wfc {
+		rules: String,
+		#[serde(default)]
+		seed: u64,
+	},
+	Dungeon(DungeonConfig),
+	Caves(CaveConfig),
+}
+
+// a map generated from scratch by running the steps in order, and the hash it must end up with
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoldenCase {
+	pub name: String,
+	pub width: usize,
+	pub height: usize,
+	#[serde(default)]
+	pub edge: EdgeMode,
+	#[serde(default, rename = "step")]
+	pub steps: Vec<GoldenStep>,
+	// empty until the case is blessed
+	#[serde(default)]
+	pub hash: String,
+}
+
+impl GoldenCase {
+	// `dir` is where relative rule paths start from
+	pub fn generate(&self, dir: &Path) -> Result<Map, MapError> {
+		let mut map = Map::filled(self.width, self.height, &GRAY)?;
+		map.set_edge_mode(self.edge);
+
+		for step in &self.steps {
+			match step {
+				GoldenStep::Noise(config) => map.generate(config),
+				GoldenStep::Biomes(config) => map.generate_biomes(config)?,
+				GoldenStep::Hydrology(config) => { map.generate_hydrology(config)?; },
+				GoldenStep::Wfc { rules, seed } => {
+					let path = dir.join(rules);
+					let rules = WfcRules::read_from_file(&path.to_string_lossy())?;
+					map.generate_wfc(&rules, &WfcOptions::with_seed(*seed))?;
+				},
+				GoldenStep::Dungeon(config) => { map.generate_dungeon(config)?; },
+				GoldenStep::Caves(config) => { map.generate_caves(config)?; },
+			}
+		}
+		Ok(map)
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenResult {
+	pub name: String,
+	pub expected: String,
+	pub found: String,
+}
+
+impl GoldenResult {
+	pub fn passed(&self) -> bool {
+		self.expected == self.found
+	}
+}
+
+
+// representative generator runs with their known hashes, a changed hash means the same seed
+// no longer gives the same map, which breaks shared seeds and replayed bug reports
+#[derive(Debug, Clone, Deserialize)]
+pub struct GoldenSuite {
+	#[serde(default, rename = "case")]
+	pub cases: Vec<GoldenCase>,
+	#[serde(skip)]
+	path: String,
+}
+
+impl GoldenSuite {
+	pub fn read_from_file(file_path: &str) -> Result<GoldenSuite, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		let mut suite: GoldenSuite = toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))?;
+		suite.path = file_path.to_string();
+		Ok(suite)
+	}
+
+	fn dir(&self) -> &Path {
+		Path::new(&self.path).parent().unwrap_or(Path::new("."))
+	}
+
+	// generates every case, a case that fails to generate fails the whole run
+	pub fn check(&self) -> Result<Vec<GoldenResult>, MapError> {
+		self.cases.iter()
+			.map(|case| Ok(GoldenResult {
+				name: case.name.clone(),
+				expected: case.hash.clone(),
+				found: case.generate(self.dir())?.content_hash_hex()?,
+			}))
+			.collect()
+	}
+
+	// writes the current hashes back into the suite file, for when a change is meant to alter
+	// the generated maps, everything else in the file is left as it was
+	pub fn bless(&mut self) -> Result<Vec<GoldenResult>, MapError> {
+		let results = self.check()?;
+
+		let source = fs::read_to_string(&self.path)
+			.map_err(|x| MapError::read_failed(&self.path, x))?;
+		let mut doc: Document = source.parse()
+			.map_err(|x: toml_edit::TomlError| MapError::parse_failed(&self.path, x.message()))?;
+
+		let cases = doc.get_mut("case")
+			.and_then(|x| x.as_array_of_tables_mut())
+			.ok_or_else(|| MapError::parse_failed(&self.path, "the suite has no [[case]] tables"))?;
+		for (table, result) in cases.iter_mut().zip(&results) {
+			table["hash"] = toml_edit::value(result.found.clone());
+		}
+		for (case, result) in self.cases.iter_mut().zip(&results) {
+			case.hash = result.found.clone();
+		}
+
+		write_atomic(&self.path, doc.to_string().as_bytes())?;
+		Ok(results)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const GOLDEN_SUITE: &str = "./assets/maps/golden.toml";
+
+	fn report(results: &[GoldenResult]) -> String {
+		results.iter()
+			.filter(|x| !x.passed())
+			.map(|x| format!("{}: expected {:?}, found {:?}", x.name, x.expected, x.found))
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+
+	#[test]
+	fn generators_match_the_golden_hashes() {
+		let suite = GoldenSuite::read_from_file(GOLDEN_SUITE).unwrap();
+		assert!(!suite.cases.is_empty());
+
+		let results = suite.check().unwrap();
+		assert_eq!(results.len(), suite.cases.len());
+		assert!(results.iter().all(|x| x.passed()), "changed golden hashes:\n{}", report(&results));
+	}
+
+	// records the current hashes once a change is meant to alter the generated maps,
+	// run it with `BLESS_GOLDEN=1 cargo test bless_golden_hashes -- --ignored`
+	#[test]
+	#[ignore]
+	fn bless_golden_hashes() {
+		if std::env::var_os("BLESS_GOLDEN").is_none() {
+			panic!("set BLESS_GOLDEN=1 to rewrite the hashes in {}", GOLDEN_SUITE);
+		}
+		let mut suite = GoldenSuite::read_from_file(GOLDEN_SUITE).unwrap();
+		let results = suite.bless().unwrap();
+		println!("{}", report(&results));
+
+		let suite = GoldenSuite::read_from_file(GOLDEN_SUITE).unwrap();
+		assert!(suite.check().unwrap().iter().all(|x| x.passed()));
+	}
+
+	#[test]
+	fn generating_twice_gives_the_same_hash() {
+		let suite = GoldenSuite::read_from_file(GOLDEN_SUITE).unwrap();
+		let dir = Path::new(GOLDEN_SUITE).parent().unwrap();
+		for case in &suite.cases {
+			let first = case.generate(dir).unwrap().content_hash().unwrap();
+			let second = case.generate(dir).unwrap().content_hash().unwrap();
+			assert_eq!(first, second, "{}", case.name);
+		}
+	}
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use serde::*;
+use super::*;
+
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+
+// 64 bit FNV-1a, unlike the std hashers the result is the same in every build and on every platform
+#[derive(Debug, Clone)]
+pub struct ContentHasher {
+	state: u64,
+}
+
+impl Default for ContentHasher {
+	fn default() -> Self {
+		ContentHasher { state: FNV_OFFSET }
+	}
+}
+
+impl ContentHasher {
+	pub fn write(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.state ^= *byte as u64;
+			self.state = self.state.wrapping_mul(FNV_PRIME);
+		}
+	}
+
+	pub fn finish(&self) -> u64 {
+		self.state
+	}
+}
+
+
+// everything besides the cells, borrowed so hashing a big map does not copy it
+#[derive(Serialize)]
+struct HashedExtras<'a> {
+	name: &'a str,
+	author: &'a str,
+	description: &'a str,
+	edge: EdgeMode,
+	spawn_points: &'a [SpawnPoint],
+	metadata: &'a BTreeMap<String, toml::Value>,
+	palette: &'a Palette,
+	objects: &'a [MapObject],
+	cell_data: &'a [CellData],
+}
+
+
+impl Map {
+	// a hash of what the map holds, the same map gives the same hash on every platform,
+	// so two machines can compare the result of a seed by exchanging a single number
+	// NOTE: the format version, history and dirty cells are left out, they are not content
+	pub fn content_hash(&self) -> Result<u64, MapError> {
+		let mut bytes = Vec::with_capacity(self.matrix.len() * 4 + 64);
+		bytes.extend_from_slice(&self.width.to_le_bytes());
+		bytes.extend_from_slice(&self.height.to_le_bytes());
+		bytes.extend_from_slice(&encode_packed(&self.matrix));
+
+		bytes.extend_from_slice(&(self.layers.len() as u16).to_le_bytes());
+		for layer in &self.layers {
+			encode_layer(&mut bytes, layer);
+		}
+
+		let extras = HashedExtras {
+			name: &self.header.name,
+			author: &self.header.author,
+			description: &self.header.description,
+			edge: self.header.edge,
+			spawn_points: &self.header.spawn_points,
+			metadata: &self.header.metadata,
+			palette: &self.palette,
+			objects: &self.objects,
+			cell_data: &self.cell_data,
+		};
+		let extras = serde_json::to_vec(&extras)
+			.map_err(|x| MapError::SerializeFailed(x.to_string()))?;
+		bytes.extend_from_slice(&(extras.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(&extras);
+
+		let mut hasher = ContentHasher::default();
+		hasher.write(&bytes);
+		Ok(hasher.finish())
+	}
+
+	// the hash as it is written down in golden files and bug reports
+	pub fn content_hash_hex(&self) -> Result<String, MapError> {
+		Ok(format!("{:016x}", self.content_hash()?))
+	}
+}
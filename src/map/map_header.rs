@@ -0,0 +1,242 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use serde::*;
+use toml_edit::Document;
+use crate::position::*;
+use super::*;
+
+
+// bump this and append a migration whenever the saved layout of a map changes
+pub const MAP_FORMAT_VERSION: u32 = 1;
+
+// MIGRATIONS[n] upgrades a version n document to version n + 1, the version number itself is
+// updated after every step
+const MIGRATIONS: [fn(&mut Document); MAP_FORMAT_VERSION as usize] = [
+	migrate_v0,
+];
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapHeader {
+	version: u32,
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub name: String,
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub author: String,
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub description: String,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub spawn_points: Vec<SpawnPoint>,
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub metadata: BTreeMap<String, toml::Value>,
+	#[serde(default, skip_serializing_if = "EdgeMode::is_void")]
+	pub(super) edge: EdgeMode,
+}
+
+impl Default for MapHeader {
+	fn default() -> Self {
+		MapHeader {
+			version: MAP_FORMAT_VERSION,
+			name: String::new(),
+			author: String::new(),
+			description: String::new(),
+			spawn_points: Vec::new(),
+			metadata: BTreeMap::new(),
+			edge: EdgeMode::Void,
+		}
+	}
+}
+
+impl MapHeader {
+	// always the current version, older files are migrated while loading
+	pub fn version(&self) -> u32 {
+		self.version
+	}
+
+	pub fn spawn_point(&self, name: &str) -> Option<&SpawnPoint> {
+		self.spawn_points.iter().find(|x| x.name == name)
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpawnPoint {
+	pub name: String,
+	pub pos: [u16; 2],
+}
+
+impl SpawnPoint {
+	pub fn new(name: &str, mx: &MxPos) -> Self {
+		SpawnPoint { name: name.to_string(), pos: [mx.hor.max(0) as u16, mx.ver.max(0) as u16] }
+	}
+
+	pub fn mx(&self) -> MxPos {
+		MxPos::new(self.pos[0] as i32, self.pos[1] as i32)
+	}
+}
+
+
+impl Map {
+	pub fn header(&self) -> &MapHeader {
+		&self.header
+	}
+
+	pub fn header_mut(&mut self) -> &mut MapHeader {
+		self.revision += 1;
+		&mut self.header
+	}
+
+	// replaces a spawn point with the same name
+	pub fn set_spawn_point(&mut self, name: &str, mx: &MxPos) -> Result<(), MapError> {
+		if self.mx_index(mx).is_none() {
+			return Err(MapError::out_of_bounds("spawn point", &format!("{:?}", mx), &format!("{}x{}", self.width, self.height)));
+		}
+
+		self.revision += 1;
+		let spawn = SpawnPoint::new(name, mx);
+		match self.header.spawn_points.iter_mut().find(|x| x.name == name) {
+			Some(existing) => *existing = spawn,
+			None => self.header.spawn_points.push(spawn),
+		}
+		Ok(())
+	}
+
+	// brings a toml map document up to the current version, documents without a header are version 0
+	// NOTE: migrations edit the document in place, so error locations still point at the original lines
+	pub(super) fn migrate<'a>(file_path: &str, source: &'a str) -> Result<Cow<'a, str>, MapError> {
+		let mut doc: Document = source.parse()
+			.map_err(|x: toml_edit::TomlError| MapError::ParseFileFailed {
+				path: file_path.to_string(),
+				message: x.message().to_string(),
+				location: x.span().map(|x| FileLocation::from_offset(source, x.start)),
+			})?;
+
+		let version = doc.get("header")
+			.and_then(|x| x.get("version"))
+			.and_then(|x| x.as_integer())
+			.unwrap_or(0);
+
+		if version == MAP_FORMAT_VERSION as i64 {
+			return Ok(Cow::Borrowed(source));
+		}
+		if version < 0 || version > MAP_FORMAT_VERSION as i64 {
+			return Err(MapError::UnsupportedVersion {
+				path: file_path.to_string(),
+				found: version,
+				supported: MAP_FORMAT_VERSION,
+			});
+		}
+
+		for (step, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+			migration(&mut doc);
+			doc["header"]["version"] = toml_edit::value(step as i64 + 1);
+		}
+		Ok(Cow::Owned(doc.to_string()))
+	}
+}
+
+
+// the original header-less format only knew width, height and the matrix, everything that was
+// added since has a default, so all it needs is a header to hold the version
+fn migrate_v0(doc: &mut Document) {
+	if !doc.contains_table("header") {
+		doc["header"] = toml_edit::table();
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const V0_MAP: &str = "# a map from before headers existed\nwidth = 2\nheight = 1\n\n[[matrix]]\npos = [0, 0]\ncolor = [10, 20, 30, 255]\n\n[[matrix]]\npos = [1, 0]\ncolor = [40, 50, 60, 255]\n";
+
+	fn temp_file(name: &str, contents: &str) -> String {
+		let path = std::env::temp_dir()
+			.join(format!("perspective_header_{}_{}.toml", name, std::process::id()))
+			.to_string_lossy()
+			.to_string();
+		std::fs::write(&path, contents).unwrap();
+		path
+	}
+
+	fn read(name: &str, contents: &str) -> Result<Map, MapError> {
+		let path = temp_file(name, contents);
+		let map = Map::read_from_file(&path);
+		std::fs::remove_file(&path).unwrap();
+		map
+	}
+
+	#[test]
+	fn header_less_files_are_migrated() {
+		let migrated = Map::migrate("v0.toml", V0_MAP).unwrap();
+		assert!(matches!(migrated, Cow::Owned(_)));
+		assert!(migrated.starts_with("# a map from before headers existed\nwidth = 2\n"));
+		assert!(migrated.contains(&format!("[header]\nversion = {}", MAP_FORMAT_VERSION)));
+
+		let map = read("v0", V0_MAP).unwrap();
+		assert_eq!(map.header().version(), MAP_FORMAT_VERSION);
+		assert_eq!(*map.header(), MapHeader::default());
+		assert_eq!(map.matrix[1].color, [40, 50, 60, 255]);
+
+		let world = Map::read_from_file("./assets/maps/world.toml").unwrap();
+		assert_eq!(world.header().version(), MAP_FORMAT_VERSION);
+	}
+
+	#[test]
+	fn current_files_are_left_alone() {
+		let current = format!("[header]\nversion = {}\n\n{}", MAP_FORMAT_VERSION, V0_MAP);
+		assert!(matches!(Map::migrate("v1.toml", &current).unwrap(), Cow::Borrowed(_)));
+	}
+
+	#[test]
+	fn newer_versions_are_rejected() {
+		for version in [MAP_FORMAT_VERSION as i64 + 1, -1] {
+			let newer = format!("[header]\nversion = {}\n\n{}", version, V0_MAP);
+			match read("newer", &newer) {
+				Err(MapError::UnsupportedVersion { path, found, supported }) => {
+					assert!(path.ends_with(".toml"));
+					assert_eq!(found, version);
+					assert_eq!(supported, MAP_FORMAT_VERSION);
+				},
+				other => panic!("expected an unsupported version, got {:?}", other),
+			}
+		}
+
+		let error = Map::migrate("new.toml", "[header]\nversion = 99\n").unwrap_err();
+		assert_eq!(error.to_string(), format!("map file 'new.toml' has format version 99, only versions up to {} are supported", MAP_FORMAT_VERSION));
+	}
+
+	#[test]
+	fn header_round_trips() {
+		let mut map = Map::filled(5, 4, &Color::from_rgba(10, 20, 30, 255)).unwrap();
+		{
+			let header = map.header_mut();
+			header.name = "harbour".to_string();
+			header.author = "someone".to_string();
+			header.description = "two lines\nof text".to_string();
+			header.metadata.insert("difficulty".to_string(), toml::Value::Integer(3));
+			header.metadata.insert("tags".to_string(), toml::Value::Array(vec!["coast".into(), "town".into()]));
+			header.metadata.insert("music".to_string(), toml::Value::String("waves.ogg".to_string()));
+		}
+		map.set_spawn_point("player", &MxPos::new(1, 2)).unwrap();
+		map.set_spawn_point("boat", &MxPos::new(4, 3)).unwrap();
+		map.set_spawn_point("player", &MxPos::new(2, 2)).unwrap();
+		map.set_edge_mode(EdgeMode::WrapHorizontal);
+		assert!(map.set_spawn_point("outside", &MxPos::new(5, 0)).is_err());
+
+		let path = temp_file("round_trip", "");
+		map.write_to_file(&path).unwrap();
+		let read_back = Map::read_from_file(&path);
+		std::fs::remove_file(&path).unwrap();
+		let read_back = read_back.unwrap();
+
+		assert_eq!(read_back.header(), map.header());
+		assert_eq!(read_back.header().spawn_point("player").unwrap().mx(), MxPos::new(2, 2));
+		assert_eq!(read_back.header().spawn_points.len(), 2);
+		assert_eq!(read_back.edge_mode(), EdgeMode::WrapHorizontal);
+
+		let from_bytes = Map::from_bytes(&map.to_bytes().unwrap()).unwrap();
+		assert_eq!(from_bytes.header(), map.header());
+	}
+}
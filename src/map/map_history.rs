@@ -0,0 +1,397 @@
+use std::collections::VecDeque;
+use super::*;
+
+
+pub const DEFAULT_HISTORY_CAPACITY: usize = 100;
+
+
+// a single reversible modification, holding both the old and the new state
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapChange {
+	Color { idx: usize, old: [u8; 4], new: [u8; 4] },
+	Layer { idx: usize, layer: String, old: LayerValue, new: LayerValue },
+	AddLayer { name: String, kind: LayerKind },
+	RemoveLayer { index: usize, layer: MapLayer },
+	ReplaceLayer { layer: String, old: LayerData, new: LayerData },
+	Palette { old: Palette, new: Palette },
+	// the entry of a cell before and after, None when the cell held nothing
+	CellData { pos: [u16; 2], old: Option<CellData>, new: Option<CellData> },
+}
+
+// one undo step, every brush stroke and transaction becomes a single command
+pub type MapCommand = Vec<MapChange>;
+
+
+#[derive(Debug)]
+pub struct MapHistory {
+	capacity: usize,
+	undo: VecDeque<MapCommand>,
+	redo: Vec<MapCommand>,
+	open: Option<MapCommand>,
+	depth: usize,
+	replaying: bool,
+}
+
+impl Default for MapHistory {
+	fn default() -> Self {
+		MapHistory {
+			capacity: DEFAULT_HISTORY_CAPACITY,
+			undo: VecDeque::new(),
+			redo: Vec::new(),
+			open: None,
+			depth: 0,
+			replaying: false,
+		}
+	}
+}
+
+impl MapHistory {
+	pub fn capacity(&self) -> usize {
+		self.capacity
+	}
+
+	pub fn can_undo(&self) -> bool {
+		!self.undo.is_empty()
+	}
+
+	pub fn can_redo(&self) -> bool {
+		!self.redo.is_empty()
+	}
+
+	pub fn undo_len(&self) -> usize {
+		self.undo.len()
+	}
+
+	pub fn redo_len(&self) -> usize {
+		self.redo.len()
+	}
+
+	pub(super) fn record(&mut self, change: MapChange) {
+		if self.replaying || self.capacity == 0 { return; }
+
+		match &mut self.open {
+			Some(command) => command.push(change),
+			None => self.push(vec![change]),
+		}
+	}
+
+	fn push(&mut self, command: MapCommand) {
+		if command.is_empty() { return; }
+
+		self.redo.clear();
+		self.undo.push_back(command);
+		while self.undo.len() > self.capacity {
+			self.undo.pop_front();
+		}
+	}
+}
+
+
+impl Map {
+	pub fn history(&self) -> &MapHistory {
+		&self.history
+	}
+
+	// a capacity of 0 turns recording off, e.g. while generating whole maps
+	pub fn set_history_capacity(&mut self, capacity: usize) {
+		self.history.capacity = capacity;
+		while self.history.undo.len() > capacity {
+			self.history.undo.pop_front();
+		}
+		if capacity == 0 {
+			self.history.redo.clear();
+		}
+	}
+
+	pub fn clear_history(&mut self) {
+		self.history.undo.clear();
+		self.history.redo.clear();
+	}
+
+	// transactions nest, only the outermost commit creates an undo step
+	pub fn begin_transaction(&mut self) {
+		if self.history.depth == 0 {
+			self.history.open = Some(Vec::new());
+		}
+		self.history.depth += 1;
+	}
+
+	pub fn commit_transaction(&mut self) {
+		if self.history.depth == 0 { return; }
+
+		self.history.depth -= 1;
+		if self.history.depth == 0 {
+			if let Some(command) = self.history.open.take() {
+				self.history.push(command);
+			}
+		}
+	}
+
+	// reverts everything changed since the outermost begin_transaction
+	pub fn abort_transaction(&mut self) {
+		if self.history.depth == 0 { return; }
+
+		self.history.depth = 0;
+		if let Some(command) = self.history.open.take() {
+			self.replay(&command, true);
+		}
+	}
+
+	// runs the closure as one undo step, rolling its changes back when it fails
+	pub fn transaction<T, F>(&mut self, edit: F) -> Result<T, MapError>
+		where F: FnOnce(&mut Map) -> Result<T, MapError>
+	{
+		self.begin_transaction();
+		match edit(self) {
+			Ok(result) => {
+				self.commit_transaction();
+				Ok(result)
+			},
+			Err(err) => {
+				self.abort_transaction();
+				Err(err)
+			},
+		}
+	}
+
+	pub fn undo(&mut self) -> bool {
+		match self.history.undo.pop_back() {
+			Some(command) => {
+				self.replay(&command, true);
+				self.history.redo.push(command);
+				true
+			},
+			None => false,
+		}
+	}
+
+	pub fn redo(&mut self) -> bool {
+		match self.history.redo.pop() {
+			Some(command) => {
+				self.replay(&command, false);
+				self.history.undo.push_back(command);
+				true
+			},
+			None => false,
+		}
+	}
+
+	pub(super) fn record(&mut self, change: MapChange) {
+		self.history.record(change);
+	}
+
+	fn replay(&mut self, command: &MapCommand, reverse: bool) {
+		self.history.replaying = true;
+
+		let changes: Box<dyn Iterator<Item = &MapChange>> = match reverse {
+			true => Box::new(command.iter().rev()),
+			false => Box::new(command.iter()),
+		};
+
+		for change in changes {
+			match (change, reverse) {
+				(MapChange::Color { idx, old, .. }, true) => self.restore_color(*idx, *old),
+				(MapChange::Color { idx, new, .. }, false) => self.restore_color(*idx, *new),
+				(MapChange::Layer { idx, layer, old, .. }, true) => self.restore_layer(*idx, layer, *old),
+				(MapChange::Layer { idx, layer, new, .. }, false) => self.restore_layer(*idx, layer, *new),
+				(MapChange::AddLayer { name, .. }, true) => { self.remove_layer(name); },
+				(MapChange::AddLayer { name, kind }, false) => { let _ = self.add_layer(name, *kind); },
+				(MapChange::RemoveLayer { index, layer }, true) => {
+					self.layers.insert((*index).min(self.layers.len()), layer.clone());
+					self.mark_all_dirty();
+				},
+				(MapChange::RemoveLayer { layer, .. }, false) => { self.remove_layer(&layer.name); },
+				(MapChange::ReplaceLayer { layer, old, .. }, true) => self.restore_layer_data(layer, old),
+				(MapChange::ReplaceLayer { layer, new, .. }, false) => self.restore_layer_data(layer, new),
+				(MapChange::Palette { old, .. }, true) => self.set_palette(old.clone()),
+				(MapChange::Palette { new, .. }, false) => self.set_palette(new.clone()),
+				(MapChange::CellData { pos, old, .. }, true) => self.restore_cell_data(&MxPos::new(pos[0] as i32, pos[1] as i32), old.clone()),
+				(MapChange::CellData { pos, new, .. }, false) => self.restore_cell_data(&MxPos::new(pos[0] as i32, pos[1] as i32), new.clone()),
+			}
+		}
+
+		self.history.replaying = false;
+	}
+
+	fn restore_color(&mut self, idx: usize, color: [u8; 4]) {
+		if let Some(value) = self.matrix.get_mut(idx) {
+			value.color = color;
+			self.mark_dirty(idx);
+		}
+	}
+
+	fn restore_layer(&mut self, idx: usize, name: &str, value: LayerValue) {
+		if let Some(layer) = self.layer_mut(name) {
+			layer.data.set(idx, value);
+			self.mark_dirty(idx);
+		}
+	}
+
+	fn restore_layer_data(&mut self, name: &str, data: &LayerData) {
+		if let Some(layer) = self.layer_mut(name) {
+			layer.data = data.clone();
+			self.mark_all_dirty();
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::position::*;
+
+	const LAYERS: [(&str, LayerKind); 3] = [
+		(ELEVATION_LAYER, LayerKind::F32),
+		("fog", LayerKind::Bool),
+		(TERRAIN_LAYER, LayerKind::U16),
+	];
+
+	fn random_mx(map: &Map, rng: &mut MapRng) -> MxPos {
+		MxPos::new(rng.below(map.width() as usize) as i32, rng.below(map.height() as usize) as i32)
+	}
+
+	fn random_color(rng: &mut MapRng) -> [u8; 4] {
+		// a handful of colours, so flood fills find regions to spread over
+		[[60, 160, 60, 255], [30, 70, 160, 255], [200, 190, 140, 255]][rng.below(3)]
+	}
+
+	fn random_value(kind: LayerKind, palette: usize, rng: &mut MapRng) -> LayerValue {
+		match kind {
+			LayerKind::U16 => LayerValue::U16(rng.below(palette.max(1)) as u16),
+			LayerKind::F32 => LayerValue::F32(rng.next_f32()),
+			LayerKind::Bool => LayerValue::Bool(rng.chance(0.5)),
+			LayerKind::U8 => LayerValue::U8(rng.below(256) as u8),
+			LayerKind::Color => LayerValue::Color(random_color(rng)),
+		}
+	}
+
+	fn random_paint(map: &Map, rng: &mut MapRng) -> Paint {
+		match map.layers().get(rng.below(map.layers().len() + 1)) {
+			Some(layer) => Paint::Layer(layer.name.clone(), random_value(layer.kind(), map.palette().len(), rng)),
+			None => Paint::Color(random_color(rng)),
+		}
+	}
+
+	fn random_edit(map: &mut Map, rng: &mut MapRng) -> Result<(), MapError> {
+		match rng.below(10) {
+			0 => { map.paint(&random_mx(map, rng), &random_paint(map, rng))?; },
+			1 => { map.fill_hex_range(&random_mx(map, rng), rng.below(3) as i32, &random_paint(map, rng))?; },
+			2 => { map.draw_line(&random_mx(map, rng), &random_mx(map, rng), &random_paint(map, rng))?; },
+			3 => { map.flood_fill(&random_mx(map, rng), &Paint::Color(random_color(rng)))?; },
+			4 => {
+				let (name, kind) = LAYERS[rng.below(LAYERS.len())];
+				let mut data = LayerData::new(kind, map.matrix.len());
+				for idx in 0..data.len() {
+					data.set(idx, random_value(kind, map.palette().len(), rng));
+				}
+				map.transaction(|map| {
+					if map.layer(name).is_none() {
+						map.add_layer(name, kind)?;
+					}
+					map.set_layer_data(name, data)
+				})?;
+			},
+			5 => {
+				let (name, _) = LAYERS[rng.below(LAYERS.len())];
+				map.remove_layer(name);
+			},
+			6 => {
+				let mut palette = map.palette().clone();
+				let name = format!("terrain_{}", palette.len());
+				palette.define(TerrainType::new(&name, random_color(rng)))?;
+				map.set_palette(palette);
+			},
+			7 if map.layer(TERRAIN_LAYER).is_some() && !map.palette().is_empty() => {
+				let name = map.palette().terrains()[rng.below(map.palette().len())].name.clone();
+				map.draw_circle(&random_mx(map, rng), rng.below(4) as i32, &Paint::Terrain(name))?;
+			},
+			8 => {
+				// a small corner, so tags and properties pile up on the same cells
+				let mx = MxPos::new(rng.below(3) as i32, rng.below(2) as i32);
+				match rng.below(4) {
+					0 => { map.tag_cell(&mx, ["spawn", "loot"][rng.below(2)])?; },
+					1 => { map.untag_cell(&mx, "spawn"); },
+					2 => { map.set_cell_property(&mx, "gold", rng.below(3) as i64)?; },
+					_ => { map.clear_cell_data(&mx); },
+				}
+			},
+			_ => {
+				map.transaction(|map| {
+					for _ in 0..3 {
+						map.paint(&random_mx(map, rng), &random_paint(map, rng))?;
+					}
+					Ok(())
+				})?;
+			},
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn random_edits_undo_and_redo_byte_for_byte() {
+		for seed in 0..25 {
+			let mut rng = MapRng::new(seed);
+			let mut map = Map::filled(11, 9, &GREEN).unwrap();
+			map.set_history_capacity(1000);
+
+			// the state after every recorded step, edits that changed nothing add no step
+			let mut states = vec![map.to_bytes().unwrap()];
+			for _ in 0..60 {
+				random_edit(&mut map, &mut rng).unwrap();
+				if map.history().undo_len() == states.len() {
+					states.push(map.to_bytes().unwrap());
+				}
+				else {
+					assert_eq!(map.history().undo_len(), states.len() - 1, "seed {}", seed);
+					assert_eq!(map.to_bytes().unwrap(), *states.last().unwrap(), "seed {}", seed);
+				}
+			}
+
+			for state in states.iter().rev().skip(1) {
+				assert!(map.undo());
+				assert_eq!(map.to_bytes().unwrap(), *state, "seed {}", seed);
+			}
+			assert!(!map.undo());
+
+			for state in states.iter().skip(1) {
+				assert!(map.redo());
+				assert_eq!(map.to_bytes().unwrap(), *state, "seed {}", seed);
+			}
+			assert!(!map.redo());
+		}
+	}
+
+	#[test]
+	fn redo_restores_layer_contents() {
+		let mut map = Map::filled(4, 4, &GREEN).unwrap();
+		map.transaction(|map| {
+			map.add_layer("fog", LayerKind::Bool)?;
+			map.set_layer_data("fog", LayerData::Bool(vec![true; 16]))?;
+			Ok(())
+		}).unwrap();
+		let filled = map.to_bytes().unwrap();
+
+		assert!(map.undo());
+		assert!(map.layer("fog").is_none());
+		assert!(map.redo());
+		assert_eq!(map.to_bytes().unwrap(), filled);
+	}
+
+	#[test]
+	fn failed_transactions_leave_no_trace() {
+		let mut map = Map::filled(4, 4, &GREEN).unwrap();
+		let before = map.to_bytes().unwrap();
+
+		let result = map.transaction(|map| {
+			map.add_layer("fog", LayerKind::Bool)?;
+			map.set_layer_data("fog", LayerData::Bool(vec![true; 16]))?;
+			map.paint(&MxPos::new(1, 1), &Paint::Color([1, 2, 3, 255]))?;
+			map.set_layer_data("fog", LayerData::U8(vec![0; 16]))
+		});
+
+		assert!(matches!(result, Err(MapError::LayerKindMismatch { .. })));
+		assert_eq!(map.to_bytes().unwrap(), before);
+		assert!(!map.history().can_undo());
+	}
+}
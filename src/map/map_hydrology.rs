@@ -0,0 +1,328 @@
+use std::{fs};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use serde::*;
+use crate::position::*;
+use super::*;
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HydrologyConfig {
+	pub seed: u64,
+	// terrains rivers flow into, cells below sea level count as water as well
+	pub water: Vec<String>,
+	pub sea_level: f32,
+	pub rivers: usize,
+	// rivers only start on cells at least this high
+	pub source_height: f32,
+	// cells that collect the water of at least this many cells widen into their neighbours
+	pub wide_flow: u32,
+	// filled depressions at least this deep become lakes, shallower ones are levelled out
+	pub lake_depth: f32,
+	pub erosion_iterations: usize,
+	// part of the drop to the next cell that is carved away per iteration for a single cell of flow
+	pub erosion_rate: f32,
+	pub river: TerrainType,
+	pub lake: TerrainType,
+}
+
+impl Default for HydrologyConfig {
+	fn default() -> Self {
+		HydrologyConfig {
+			seed: 0,
+			water: vec!["ocean".to_string()],
+			sea_level: 0.0,
+			rivers: 12,
+			source_height: 0.6,
+			wide_flow: 60,
+			lake_depth: 0.05,
+			erosion_iterations: 0,
+			erosion_rate: 0.05,
+			river: TerrainType { movement_cost: 2.0, ..TerrainType::new("river", [50, 110, 200, 255]) },
+			lake: TerrainType { movement_cost: 0.0, ..TerrainType::new("lake", [40, 90, 180, 255]) },
+		}
+	}
+}
+
+impl HydrologyConfig {
+	pub fn read_from_file(file_path: &str) -> Result<HydrologyConfig, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))
+	}
+}
+
+
+// what a hydrology pass added, river paths run from the source to the first water or lake cell
+#[derive(Debug, Clone, Default)]
+pub struct HydrologyReport {
+	pub rivers: Vec<Vec<MxPos>>,
+	pub lakes: Vec<MxPos>,
+	// number of cells that drain through each cell, itself included, by cell index
+	pub flow: Vec<u32>,
+}
+
+
+// the lowest cell comes out of the heap first, ties go to the lowest index to stay deterministic
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FloodCell {
+	height: f32,
+	idx: usize,
+}
+
+impl Eq for FloodCell {}
+
+impl Ord for FloodCell {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other.height.total_cmp(&self.height).then(other.idx.cmp(&self.idx))
+	}
+}
+
+impl PartialOrd for FloodCell {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+// the result of a priority flood from the outlets, every cell drains into its `down` cell
+struct Drainage {
+	filled: Vec<f32>,
+	down: Vec<Option<usize>>,
+	// cells in the order they were reached, downstream cells always come first
+	order: Vec<usize>,
+}
+
+impl Drainage {
+	fn flow(&self) -> Vec<u32> {
+		let mut flow = vec![1u32; self.filled.len()];
+		for idx in self.order.iter().rev() {
+			if let Some(down) = self.down[*idx] {
+				flow[down] += flow[*idx];
+			}
+		}
+		flow
+	}
+}
+
+
+impl Map {
+	// fills depressions into lakes, carves rivers from high cells down to the water and
+	// optionally erodes the elevation layer first, like generators this clears the history
+	// NOTE: maps without a terrain layer only get river and lake colours
+	pub fn generate_hydrology(&mut self, config: &HydrologyConfig) -> Result<HydrologyReport, MapError> {
+		let mut heights = match self.layer(ELEVATION_LAYER).map(|x| &x.data) {
+			Some(LayerData::F32(heights)) => heights.clone(),
+			Some(data) => return Err(MapError::LayerKindMismatch {
+				layer: ELEVATION_LAYER.to_string(),
+				expected: LayerKind::F32,
+				found: data.kind(),
+			}),
+			None => return Err(MapError::UnknownLayer(ELEVATION_LAYER.to_string())),
+		};
+
+		let water: Vec<bool> = self.positions()
+			.map(|mx| self.terrain_at_mx(&mx).is_some_and(|x| config.water.contains(&x.name))
+				|| self.elevation_at_mx(&mx) < config.sea_level)
+			.collect();
+
+		for _ in 0..config.erosion_iterations {
+			let drainage = self.drainage(&heights, &water);
+			let flow = drainage.flow();
+			for idx in drainage.order.iter().rev() {
+				let down = match drainage.down[*idx] {
+					Some(down) if !water[*idx] => down,
+					_ => continue,
+				};
+				let drop = heights[*idx] - heights[down];
+				if drop > 0.0 {
+					let carve = (config.erosion_rate * (flow[*idx] as f32).sqrt()).min(1.0);
+					heights[*idx] -= drop * carve;
+				}
+			}
+		}
+
+		// levelling every depression keeps rivers from flowing uphill, deep ones become lakes
+		let drainage = self.drainage(&heights, &water);
+		let flow = drainage.flow();
+		let lake: Vec<bool> = (0..heights.len())
+			.map(|idx| !water[idx] && drainage.filled[idx] - heights[idx] >= config.lake_depth)
+			.collect();
+		let heights = drainage.filled.clone();
+
+		let mut sources: Vec<usize> = (0..heights.len())
+			.filter(|x| !water[*x] && !lake[*x] && heights[*x] >= config.source_height)
+			.collect();
+		let mut rng = MapRng::new(config.seed);
+		rng.shuffle(&mut sources);
+
+		let mut report = HydrologyReport {
+			lakes: (0..heights.len()).filter(|x| lake[*x]).map(|x| self.mx_of(x)).collect(),
+			..Default::default()
+		};
+		let mut river = vec![false; heights.len()];
+		for source in sources.into_iter().take(config.rivers) {
+			let mut path = vec![self.mx_of(source)];
+			let mut idx = source;
+			river[idx] = true;
+
+			while let Some(down) = drainage.down[idx].filter(|_| !water[idx] && !lake[idx]) {
+				idx = down;
+				path.push(self.mx_of(idx));
+				if !water[idx] && !lake[idx] {
+					river[idx] = true;
+				}
+			}
+			report.rivers.push(path);
+		}
+
+		// big rivers spill into the neighbouring land cells
+		let channels: Vec<usize> = (0..heights.len()).filter(|x| river[*x] && flow[*x] >= config.wide_flow).collect();
+		for idx in channels {
+			for next in self.neighbours_at_mx(&self.mx_of(idx)) {
+				let next = self.mx_index(&next).unwrap();
+				if !water[next] && !lake[next] {
+					river[next] = true;
+				}
+			}
+		}
+		report.flow = flow;
+
+		let mut palette = self.palette.clone();
+		let river_terrain = palette.define(config.river.clone())?;
+		let lake_terrain = palette.define(config.lake.clone())?;
+
+		let mut terrains = self.layer(TERRAIN_LAYER).map(|x| x.data().clone());
+		for idx in 0..heights.len() {
+			let terrain = match (lake[idx], river[idx]) {
+				(true, _) => (lake_terrain, config.lake.color),
+				(false, true) => (river_terrain, config.river.color),
+				_ => continue,
+			};
+			self.matrix[idx].color = terrain.1;
+			if let Some(terrains) = &mut terrains {
+				terrains.set(idx, LayerValue::U16(terrain.0));
+			}
+		}
+
+		if let Some(terrains) = terrains {
+			self.palette = palette;
+			self.replace_layer(TERRAIN_LAYER, terrains);
+		}
+		self.replace_layer(ELEVATION_LAYER, LayerData::F32(heights));
+		self.clear_history();
+		self.mark_all_dirty();
+		Ok(report)
+	}
+
+	// floods the height field from the water, or from the border when there is none
+	fn drainage(&self, heights: &[f32], water: &[bool]) -> Drainage {
+		let len = heights.len();
+		let mut filled = heights.to_vec();
+		let mut down = vec![None; len];
+		let mut order = Vec::with_capacity(len);
+		let mut seen = vec![false; len];
+		let mut heap = BinaryHeap::new();
+
+		let mut outlets: Vec<usize> = (0..len).filter(|x| water[*x]).collect();
+		if outlets.is_empty() {
+			outlets = (0..len).filter(|x| self.neighbours_at_mx(&self.mx_of(*x)).len() < 6).collect();
+		}
+		if outlets.is_empty() {
+			outlets.extend((0..len).min_by(|a, b| heights[*a].total_cmp(&heights[*b])));
+		}
+
+		for idx in outlets {
+			seen[idx] = true;
+			heap.push(FloodCell { height: heights[idx], idx });
+		}
+
+		while let Some(cell) = heap.pop() {
+			order.push(cell.idx);
+			for next in self.neighbours_at_mx(&self.mx_of(cell.idx)) {
+				let next = self.mx_index(&next).unwrap();
+				if seen[next] { continue; }
+
+				seen[next] = true;
+				filled[next] = heights[next].max(filled[cell.idx]);
+				down[next] = Some(cell.idx);
+				heap.push(FloodCell { height: filled[next], idx: next });
+			}
+		}
+		Drainage { filled, down, order }
+	}
+
+	pub(super) fn mx_of(&self, idx: usize) -> MxPos {
+		let width = self.width.max(1) as usize;
+		MxPos::new((idx % width) as i32, (idx / width) as i32)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::map::map_biomes::BiomeConfig;
+
+	fn world(seed: u32, erosion_iterations: usize) -> (Map, HydrologyReport) {
+		let mut map = Map::with_biomes(48, 36, &BiomeConfig { seed, height_scale: 2.0, ..Default::default() }).unwrap();
+		let config = HydrologyConfig { seed: seed as u64, erosion_iterations, ..Default::default() };
+		let report = map.generate_hydrology(&config).unwrap();
+		(map, report)
+	}
+
+	#[test]
+	fn rivers_end_in_water_or_lakes() {
+		for seed in 0..6 {
+			let (map, report) = world(seed, seed as usize % 3);
+			assert!(!report.rivers.is_empty(), "seed {}", seed);
+
+			for river in &report.rivers {
+				let end = river.last().unwrap();
+				let terrain = map.terrain_at_mx(end).unwrap();
+				assert!(terrain.name == "ocean" || report.lakes.contains(end), "seed {} ends on {} at {:?}", seed, terrain.name, end);
+
+				// only the last cell lies in the water
+				for mx in &river[..river.len() - 1] {
+					assert_eq!(map.terrain_at_mx(mx).unwrap().name, "river", "seed {} at {:?}", seed, mx);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn rivers_never_flow_uphill() {
+		for seed in 0..6 {
+			let (map, report) = world(seed, seed as usize % 3);
+
+			for river in &report.rivers {
+				for step in river.windows(2) {
+					assert!(map.neighbours_at_mx(&step[0]).contains(&step[1]), "seed {} jumps from {:?} to {:?}", seed, step[0], step[1]);
+					assert!(map.elevation_at_mx(&step[1]) <= map.elevation_at_mx(&step[0]), "seed {} flows uphill at {:?}", seed, step[1]);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn lakes_fill_depressions_to_a_flat_surface() {
+		for seed in 0..6 {
+			let (map, report) = world(seed, 0);
+			let lakes = map.label_regions(|_, mx| report.lakes.contains(mx));
+
+			for lake in lakes.regions() {
+				let level = map.elevation_at_mx(&lake.cells[0]);
+				assert!(lake.cells.iter().all(|x| map.elevation_at_mx(x) == level), "seed {}", seed);
+			}
+		}
+	}
+
+	#[test]
+	fn same_seed_same_water() {
+		let (a, _) = world(3, 2);
+		let (b, _) = world(3, 2);
+		assert_eq!(a.to_bytes().unwrap(), b.to_bytes().unwrap());
+	}
+}
@@ -0,0 +1,152 @@
+use std::{fs};
+use std::io::Cursor;
+use image::{ImageFormat, RgbaImage};
+use crate::position::*;
+use super::*;
+
+
+// every cell covers cell_size x cell_size pixels, odd rows are shifted right by half a cell,
+// so exported images are half a cell wider than the map and a cell size of 1 maps pixels to cells
+impl Map {
+	pub fn from_image(file_path: &str, cell_size: u32) -> Result<Map, MapError> {
+		let bytes = fs::read(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		Map::from_image_bytes(&bytes, cell_size).map_err(|x| x.at_path(file_path))
+	}
+
+	pub fn from_image_bytes(bytes: &[u8], cell_size: u32) -> Result<Map, MapError> {
+		let cell_size = cell_size.max(1);
+		let image = image::load_from_memory_with_format(bytes, ImageFormat::Png)
+			.map_err(|x| MapError::parse_failed("<bytes>", &x.to_string()))?
+			.to_rgba8();
+
+		let width = image.width() / cell_size;
+		let height = image.height() / cell_size;
+		let mut map = Map::filled(width as usize, height as usize, &BLANK)?;
+
+		for ver in 0..height {
+		for hor in 0..width {
+			let (left, top) = cell_origin(&MxPos::new(hor as i32, ver as i32), cell_size);
+
+			// NOTE: the pixel left of the cell center, which is still inside the cell for odd rows
+			let x = (left + cell_size.div_ceil(2) - 1).min(image.width() - 1);
+			let y = top + cell_size.div_ceil(2) - 1;
+			map.matrix[(ver * width + hor) as usize].color = image.get_pixel(x, y).0;
+		}}
+
+		Ok(map)
+	}
+
+	pub fn to_image(&self, file_path: &str, cell_size: u32) -> Result<(), MapError> {
+		write_atomic(file_path, &self.to_image_bytes(cell_size)?)
+	}
+
+	// png encoded, pixels that no cell covers are left transparent
+	pub fn to_image_bytes(&self, cell_size: u32) -> Result<Vec<u8>, MapError> {
+		let cell_size = cell_size.max(1);
+		let width = self.width.max(0) as u32;
+		let height = self.height.max(0) as u32;
+		let shift = match height > 1 { true => cell_size / 2, false => 0 };
+		let mut image = RgbaImage::new(width * cell_size + shift, height * cell_size);
+
+		for ver in 0..height {
+		for hor in 0..width {
+			let mx = MxPos::new(hor as i32, ver as i32);
+			let color = match self.terrain_at_mx(&mx) {
+				Some(terrain) => terrain.color,
+				None => self.matrix[(ver * width + hor) as usize].color,
+			};
+
+			let (left, top) = cell_origin(&mx, cell_size);
+			for y in top..top + cell_size {
+			for x in left..left + cell_size {
+				image.put_pixel(x, y, image::Rgba(color));
+			}}
+		}}
+
+		let mut bytes = Cursor::new(Vec::new());
+		image.write_to(&mut bytes, ImageFormat::Png)
+			.map_err(|x| MapError::SerializeFailed(x.to_string()))?;
+		Ok(bytes.into_inner())
+	}
+}
+
+fn cell_origin(mx: &MxPos, cell_size: u32) -> (u32, u32) {
+	let shift = match mx.ver & 1 { 1 => cell_size / 2, _ => 0 };
+	(mx.hor as u32 * cell_size + shift, mx.ver as u32 * cell_size)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// every cell gets its own color, with one transparent cell
+	fn sample_map() -> Map {
+		let mut map = Map::filled(5, 3, &BLANK).unwrap();
+		for ver in 0..3 {
+		for hor in 0..5 {
+			let color = [hor as u8 * 40 + 10, ver as u8 * 60 + 20, 90, 255];
+			map.paint(&MxPos::new(hor, ver), &Paint::Color(color)).unwrap();
+		}}
+		map.paint(&MxPos::new(3, 1), &Paint::Color([0, 0, 0, 0])).unwrap();
+		map
+	}
+
+	fn colors(map: &Map) -> Vec<[u8; 4]> {
+		map.matrix.iter().map(|x| x.color).collect()
+	}
+
+	#[test]
+	fn images_round_trip() {
+		let map = sample_map();
+		for cell_size in [1, 2, 3, 4, 7] {
+			let bytes = map.to_image_bytes(cell_size).unwrap();
+			let read_back = Map::from_image_bytes(&bytes, cell_size).unwrap();
+			assert_eq!((read_back.width, read_back.height), (5, 3), "cell size {}", cell_size);
+			assert_eq!(colors(&read_back), colors(&map), "cell size {}", cell_size);
+		}
+	}
+
+	#[test]
+	fn pixels_follow_the_hex_layout() {
+		let map = sample_map();
+		let image = image::load_from_memory(&map.to_image_bytes(4).unwrap()).unwrap().to_rgba8();
+		assert_eq!(image.dimensions(), (5 * 4 + 2, 3 * 4));
+
+		// even rows start at the left edge, odd rows half a cell in
+		assert_eq!(image.get_pixel(0, 0).0, [10, 20, 90, 255]);
+		assert_eq!(image.get_pixel(0, 4).0, [0, 0, 0, 0]);
+		assert_eq!(image.get_pixel(1, 4).0, [0, 0, 0, 0]);
+		assert_eq!(image.get_pixel(2, 4).0, [10, 80, 90, 255]);
+		assert_eq!(image.get_pixel(21, 4).0, [170, 80, 90, 255]);
+		assert_eq!(image.get_pixel(21, 8).0, [0, 0, 0, 0]);
+		assert_eq!(image.get_pixel(19, 8).0, [170, 140, 90, 255]);
+		// the transparent cell at (3, 1)
+		assert_eq!(image.get_pixel(14, 5).0, [0, 0, 0, 0]);
+		assert_eq!(image.get_pixel(18, 5).0, [170, 80, 90, 255]);
+
+		// a cell size of 1 maps pixels to cells
+		let image = image::load_from_memory(&map.to_image_bytes(1).unwrap()).unwrap().to_rgba8();
+		assert_eq!(image.dimensions(), (5, 3));
+		assert_eq!(image.get_pixel(4, 1).0, [170, 80, 90, 255]);
+		assert_eq!(image.get_pixel(3, 1).0, [0, 0, 0, 0]);
+	}
+
+	#[test]
+	fn images_from_files() {
+		let path = std::env::temp_dir()
+			.join(format!("perspective_images_from_files_{}.png", std::process::id()))
+			.to_string_lossy()
+			.to_string();
+		let map = sample_map();
+		map.to_image(&path, 3).unwrap();
+		let read_back = Map::from_image(&path, 3);
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(colors(&read_back.unwrap()), colors(&map));
+
+		assert!(Map::from_image(&path, 3).is_err());
+		assert!(Map::from_image_bytes(b"not a png", 3).is_err());
+	}
+}
@@ -0,0 +1,295 @@
+use std::fmt;
+use serde::*;
+use crate::position::*;
+use super::*;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerKind {
+	U8,
+	U16,
+	F32,
+	Color,
+	Bool,
+}
+
+impl LayerKind {
+	pub fn default_value(&self) -> LayerValue {
+		match self {
+			LayerKind::U8 => LayerValue::U8(0),
+			LayerKind::U16 => LayerValue::U16(0),
+			LayerKind::F32 => LayerValue::F32(0.0),
+			LayerKind::Color => LayerValue::Color([0, 0, 0, 0]),
+			LayerKind::Bool => LayerValue::Bool(false),
+		}
+	}
+}
+
+impl fmt::Display for LayerKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LayerKind::U8 => write!(f, "u8"),
+			LayerKind::U16 => write!(f, "u16"),
+			LayerKind::F32 => write!(f, "f32"),
+			LayerKind::Color => write!(f, "color"),
+			LayerKind::Bool => write!(f, "bool"),
+		}
+	}
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerValue {
+	U8(u8),
+	U16(u16),
+	F32(f32),
+	Color([u8; 4]),
+	Bool(bool),
+}
+
+impl LayerValue {
+	pub fn kind(&self) -> LayerKind {
+		match self {
+			LayerValue::U8(_) => LayerKind::U8,
+			LayerValue::U16(_) => LayerKind::U16,
+			LayerValue::F32(_) => LayerKind::F32,
+			LayerValue::Color(_) => LayerKind::Color,
+			LayerValue::Bool(_) => LayerKind::Bool,
+		}
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "values", rename_all = "lowercase")]
+pub enum LayerData {
+	U8(Vec<u8>),
+	U16(Vec<u16>),
+	F32(Vec<f32>),
+	Color(Vec<[u8; 4]>),
+	Bool(Vec<bool>),
+}
+
+impl LayerData {
+	pub fn new(kind: LayerKind, len: usize) -> Self {
+		LayerData::filled(kind.default_value(), len)
+	}
+
+	pub fn filled(value: LayerValue, len: usize) -> Self {
+		match value {
+			LayerValue::U8(x) => LayerData::U8(vec![x; len]),
+			LayerValue::U16(x) => LayerData::U16(vec![x; len]),
+			LayerValue::F32(x) => LayerData::F32(vec![x; len]),
+			LayerValue::Color(x) => LayerData::Color(vec![x; len]),
+			LayerValue::Bool(x) => LayerData::Bool(vec![x; len]),
+		}
+	}
+
+	pub fn kind(&self) -> LayerKind {
+		match self {
+			LayerData::U8(_) => LayerKind::U8,
+			LayerData::U16(_) => LayerKind::U16,
+			LayerData::F32(_) => LayerKind::F32,
+			LayerData::Color(_) => LayerKind::Color,
+			LayerData::Bool(_) => LayerKind::Bool,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		match self {
+			LayerData::U8(v) => v.len(),
+			LayerData::U16(v) => v.len(),
+			LayerData::F32(v) => v.len(),
+			LayerData::Color(v) => v.len(),
+			LayerData::Bool(v) => v.len(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn get(&self, idx: usize) -> Option<LayerValue> {
+		match self {
+			LayerData::U8(v) => v.get(idx).map(|x| LayerValue::U8(*x)),
+			LayerData::U16(v) => v.get(idx).map(|x| LayerValue::U16(*x)),
+			LayerData::F32(v) => v.get(idx).map(|x| LayerValue::F32(*x)),
+			LayerData::Color(v) => v.get(idx).map(|x| LayerValue::Color(*x)),
+			LayerData::Bool(v) => v.get(idx).map(|x| LayerValue::Bool(*x)),
+		}
+	}
+
+	// returns false when the index is out of range or the value is of a different kind
+	pub fn set(&mut self, idx: usize, value: LayerValue) -> bool {
+		if idx >= self.len() { return false; }
+
+		match (self, value) {
+			(LayerData::U8(v), LayerValue::U8(x)) => v[idx] = x,
+			(LayerData::U16(v), LayerValue::U16(x)) => v[idx] = x,
+			(LayerData::F32(v), LayerValue::F32(x)) => v[idx] = x,
+			(LayerData::Color(v), LayerValue::Color(x)) => v[idx] = x,
+			(LayerData::Bool(v), LayerValue::Bool(x)) => v[idx] = x,
+			_ => return false,
+		}
+		true
+	}
+
+	// a new layer holding the value of `sources[idx]` at every idx, default values where
+	// there is no source
+	pub fn gather(&self, sources: &[Option<usize>]) -> LayerData {
+		let mut data = LayerData::new(self.kind(), sources.len());
+		for (idx, source) in sources.iter().enumerate() {
+			if let Some(value) = source.and_then(|x| self.get(x)) {
+				data.set(idx, value);
+			}
+		}
+		data
+	}
+
+	pub fn resize(&mut self, len: usize) {
+		match self {
+			LayerData::U8(v) => v.resize(len, 0),
+			LayerData::U16(v) => v.resize(len, 0),
+			LayerData::F32(v) => v.resize(len, 0.0),
+			LayerData::Color(v) => v.resize(len, [0, 0, 0, 0]),
+			LayerData::Bool(v) => v.resize(len, false),
+		}
+	}
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapLayer {
+	pub name: String,
+	// NOTE: only written through the map, so every edit is recorded and marks its cells dirty
+	#[serde(flatten)]
+	pub(super) data: LayerData,
+}
+
+impl MapLayer {
+	pub fn new(name: &str, kind: LayerKind, len: usize) -> Self {
+		MapLayer {
+			name: name.to_string(),
+			data: LayerData::new(kind, len),
+		}
+	}
+
+	pub fn kind(&self) -> LayerKind {
+		self.data.kind()
+	}
+
+	pub fn data(&self) -> &LayerData {
+		&self.data
+	}
+
+	pub fn get(&self, idx: usize) -> Option<LayerValue> {
+		self.data.get(idx)
+	}
+}
+
+
+impl Map {
+	pub fn layers(&self) -> &Vec<MapLayer> {
+		&self.layers
+	}
+
+	pub fn layer(&self, name: &str) -> Option<&MapLayer> {
+		self.layers.iter().find(|x| x.name == name)
+	}
+
+	// bypasses the history and the dirty cells, edits go through `paint` or `set_layer_data`
+	pub(super) fn layer_mut(&mut self, name: &str) -> Option<&mut MapLayer> {
+		self.layers.iter_mut().find(|x| x.name == name)
+	}
+
+	// the new layer holds default values, fill it through `set_layer_data` or painting
+	pub fn add_layer(&mut self, name: &str, kind: LayerKind) -> Result<(), MapError> {
+		if self.layer(name).is_some() {
+			return Err(MapError::LayerExists(name.to_string()));
+		}
+
+		self.record(MapChange::AddLayer { name: name.to_string(), kind });
+		self.mark_all_dirty();
+		self.layers.push(MapLayer::new(name, kind, self.matrix.len()));
+		Ok(())
+	}
+
+	pub fn remove_layer(&mut self, name: &str) -> Option<MapLayer> {
+		let index = self.layers.iter().position(|x| x.name == name)?;
+		let layer = self.layers.remove(index);
+		self.record(MapChange::RemoveLayer { index, layer: layer.clone() });
+		self.mark_all_dirty();
+		Some(layer)
+	}
+
+	// overwrites every value of a layer as a single change, returns the old values
+	pub fn set_layer_data(&mut self, name: &str, data: LayerData) -> Result<LayerData, MapError> {
+		let len = self.matrix.len();
+		let layer = self.layer_mut(name)
+			.ok_or_else(|| MapError::UnknownLayer(name.to_string()))?;
+
+		if layer.kind() != data.kind() {
+			return Err(MapError::LayerKindMismatch { layer: name.to_string(), expected: layer.kind(), found: data.kind() });
+		}
+		if data.len() != len {
+			return Err(MapError::OutOfBounds(format!("layer '{}' needs {} values, got {}", name, len, data.len())));
+		}
+
+		let old = std::mem::replace(&mut layer.data, data.clone());
+		self.record(MapChange::ReplaceLayer { layer: name.to_string(), old: old.clone(), new: data });
+		self.mark_all_dirty();
+		Ok(old)
+	}
+
+	pub fn get_layer_at_mx(&self, name: &str, mx: &MxPos) -> Option<LayerValue> {
+		self.layer(name)?.data.get(self.read_index(mx)?)
+	}
+
+	pub fn set_layer_at_mx(&mut self, name: &str, mx: &MxPos, value: LayerValue) -> Result<(), MapError> {
+		self.paint(mx, &Paint::Layer(name.to_string(), value)).map(|_| ())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn layer_edits_are_recorded_and_bump_the_revision() {
+		let mut map = Map::filled(4, 4, &GRAY).unwrap();
+		map.add_layer("fog", LayerKind::Bool).unwrap();
+		map.take_dirty();
+		let revision = map.revision();
+
+		let mx = MxPos::new(2, 1);
+		map.set_layer_at_mx("fog", &mx, LayerValue::Bool(true)).unwrap();
+		assert!(map.revision() > revision);
+		assert!(map.dirty_cells().contains(&mx));
+		assert_eq!(map.layer("fog").unwrap().get(6), Some(LayerValue::Bool(true)));
+
+		let revision = map.revision();
+		let old = map.set_layer_data("fog", LayerData::filled(LayerValue::Bool(true), 16)).unwrap();
+		assert!(map.revision() > revision);
+		assert_eq!(old.get(6), Some(LayerValue::Bool(true)));
+		assert_eq!(old.get(5), Some(LayerValue::Bool(false)));
+
+		assert!(map.undo());
+		assert_eq!(map.layer("fog").unwrap().data(), &old);
+		assert!(map.undo());
+		assert_eq!(map.get_layer_at_mx("fog", &mx), Some(LayerValue::Bool(false)));
+	}
+
+	#[test]
+	fn bad_layer_data_is_rejected() {
+		let mut map = Map::filled(3, 2, &GRAY).unwrap();
+		map.add_layer("height", LayerKind::U8).unwrap();
+
+		assert!(matches!(map.set_layer_data("depth", LayerData::new(LayerKind::U8, 6)), Err(MapError::UnknownLayer(_))));
+		assert!(matches!(map.set_layer_data("height", LayerData::new(LayerKind::F32, 6)), Err(MapError::LayerKindMismatch { .. })));
+		assert!(matches!(map.set_layer_data("height", LayerData::new(LayerKind::U8, 5)), Err(MapError::OutOfBounds(_))));
+		assert!(matches!(map.set_layer_at_mx("height", &MxPos::new(0, 0), LayerValue::Bool(true)), Err(MapError::LayerKindMismatch { .. })));
+		assert_eq!(map.history().undo_len(), 1);
+	}
+}
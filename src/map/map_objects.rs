@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use serde::*;
+use crate::position::*;
+use super::*;
+
+
+// a named point of interest on a cell, like a spawn point, door or sign
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapObject {
+	pub name: String,
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub kind: String,
+	// the editor layer the object was placed on
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub group: String,
+	pub pos: [u16; 2],
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub properties: BTreeMap<String, toml::Value>,
+}
+
+impl MapObject {
+	pub fn new(name: &str, mx: &MxPos) -> Self {
+		MapObject {
+			name: name.to_string(),
+			kind: String::new(),
+			group: String::new(),
+			pos: [mx.hor.max(0) as u16, mx.ver.max(0) as u16],
+			properties: BTreeMap::new(),
+		}
+	}
+
+	pub fn mx(&self) -> MxPos {
+		MxPos::new(self.pos[0] as i32, self.pos[1] as i32)
+	}
+}
+
+
+// NOTE: objects are not drawn and changes to them are not recorded in the history
+impl Map {
+	pub fn objects(&self) -> &Vec<MapObject> {
+		&self.objects
+	}
+
+	pub fn objects_mut(&mut self) -> &mut Vec<MapObject> {
+		self.revision += 1;
+		&mut self.objects
+	}
+
+	pub fn add_object(&mut self, object: MapObject) -> Result<&mut MapObject, MapError> {
+		if self.mx_index(&object.mx()).is_none() {
+			return Err(MapError::out_of_bounds("object", &format!("{:?}", object.pos), &format!("{}x{}", self.width, self.height)));
+		}
+		self.revision += 1;
+		self.objects.push(object);
+		Ok(self.objects.last_mut().unwrap())
+	}
+
+	pub fn objects_at_mx(&self, mx: &MxPos) -> Vec<&MapObject> {
+		self.objects.iter().filter(|x| x.mx() == *mx).collect()
+	}
+
+	pub fn object(&self, name: &str) -> Option<&MapObject> {
+		self.objects.iter().find(|x| x.name == name)
+	}
+}
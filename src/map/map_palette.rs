@@ -0,0 +1,250 @@
+use std::{fs};
+use std::collections::BTreeMap;
+use serde::*;
+use macroquad::prelude::*;
+use crate::position::*;
+use super::*;
+
+
+pub const TERRAIN_LAYER: &str = "terrain";
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TerrainType {
+	pub name: String,
+	pub color: [u8; 4],
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sprite: Option<String>,
+	#[serde(default = "default_movement_cost")]
+	pub movement_cost: f32,
+	// 0.0 lets all light and sight through, 1.0 blocks it completely
+	#[serde(default)]
+	pub opacity: f32,
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub properties: BTreeMap<String, toml::Value>,
+}
+
+fn default_movement_cost() -> f32 { 1.0 }
+
+impl TerrainType {
+	pub fn new(name: &str, color: [u8; 4]) -> Self {
+		TerrainType {
+			name: name.to_string(),
+			color,
+			sprite: None,
+			movement_cost: default_movement_cost(),
+			opacity: 0.0,
+			properties: BTreeMap::new(),
+		}
+	}
+
+	pub fn color(&self) -> Color {
+		MapValue { pos: [0, 0], color: self.color }.color()
+	}
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Palette {
+	terrains: Vec<TerrainType>,
+}
+
+// a standalone palette file is a list of [[terrain]] tables
+#[derive(Serialize, Deserialize)]
+struct PaletteFile {
+	#[serde(default)]
+	terrain: Vec<TerrainType>,
+}
+
+impl Palette {
+	pub fn new() -> Self {
+		Palette::default()
+	}
+
+	pub fn read_from_file(file_path: &str) -> Result<Palette, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		let file: PaletteFile = toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))?;
+
+		Ok(Palette { terrains: file.terrain })
+	}
+
+	pub fn write_to_file(&self, file_path: &str) -> Result<(), MapError> {
+		let file = PaletteFile { terrain: self.terrains.clone() };
+		let toml = toml::to_string(&file)
+			.map_err(|x| MapError::SerializeFailed(x.to_string()))?;
+
+		write_atomic(file_path, toml.as_bytes())
+	}
+
+	pub fn len(&self) -> usize {
+		self.terrains.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.terrains.is_empty()
+	}
+
+	pub fn terrains(&self) -> &Vec<TerrainType> {
+		&self.terrains
+	}
+
+	pub fn get(&self, idx: u16) -> Option<&TerrainType> {
+		self.terrains.get(idx as usize)
+	}
+
+	pub fn get_mut(&mut self, idx: u16) -> Option<&mut TerrainType> {
+		self.terrains.get_mut(idx as usize)
+	}
+
+	pub fn index_of(&self, name: &str) -> Option<u16> {
+		self.terrains.iter().position(|x| x.name == name).map(|x| x as u16)
+	}
+
+	pub fn by_name(&self, name: &str) -> Option<&TerrainType> {
+		self.terrains.iter().find(|x| x.name == name)
+	}
+
+	// replaces a terrain with the same name, so indices stay stable when redefining
+	pub fn define(&mut self, terrain: TerrainType) -> Result<u16, MapError> {
+		if let Some(idx) = self.index_of(&terrain.name) {
+			self.terrains[idx as usize] = terrain;
+			return Ok(idx);
+		}
+		if self.terrains.len() >= u16::MAX as usize {
+			return Err(MapError::out_of_bounds("palette", &self.terrains.len().to_string(), &u16::MAX.to_string()));
+		}
+		self.terrains.push(terrain);
+		Ok(self.terrains.len() as u16 - 1)
+	}
+}
+
+
+impl Map {
+	pub fn palette(&self) -> &Palette {
+		&self.palette
+	}
+
+	// NOTE: changes made through here are not recorded in the history, use set_palette for that
+	pub fn palette_mut(&mut self) -> &mut Palette {
+		self.mark_all_dirty();
+		&mut self.palette
+	}
+
+	pub fn set_palette(&mut self, palette: Palette) {
+		self.record(MapChange::Palette { old: self.palette.clone(), new: palette.clone() });
+		self.mark_all_dirty();
+		self.palette = palette;
+	}
+
+	pub fn terrain_index_at_mx(&self, mx: &MxPos) -> Option<u16> {
+		match self.get_layer_at_mx(TERRAIN_LAYER, mx)? {
+			LayerValue::U16(idx) => Some(idx),
+			_ => None,
+		}
+	}
+
+	pub fn terrain_at_mx(&self, mx: &MxPos) -> Option<&TerrainType> {
+		self.palette.get(self.terrain_index_at_mx(mx)?)
+	}
+
+	pub fn set_terrain_at_mx(&mut self, mx: &MxPos, name: &str) -> Result<(), MapError> {
+		self.paint(mx, &Paint::Terrain(name.to_string())).map(|_| ())
+	}
+
+	// the palette colour of the cell when it references a terrain, the raw cell colour otherwise
+	pub fn terrain_color_at_mx(&self, mx: &MxPos) -> Option<Color> {
+		match self.terrain_at_mx(mx) {
+			Some(terrain) => Some(terrain.color()),
+			None => self.get_at_mx(mx),
+		}
+	}
+
+	// migrates a colour-only map: every distinct cell colour becomes a palette entry
+	// and the terrain layer points each cell to it, returns the number of new entries
+	// NOTE: the migration is a single undo step
+	pub fn build_palette_from_colors(&mut self) -> Result<usize, MapError> {
+		let mut palette = self.palette.clone();
+		let mut indices = Vec::with_capacity(self.matrix.len());
+
+		for value in &self.matrix {
+			let existing = palette.terrains.iter().position(|x| x.color == value.color);
+			let idx = match existing {
+				Some(idx) => idx as u16,
+				None => {
+					let name = format!("terrain_{:02x}{:02x}{:02x}{:02x}",
+						value.color[0], value.color[1], value.color[2], value.color[3]);
+					palette.define(TerrainType::new(&name, value.color))?
+				},
+			};
+			indices.push(idx);
+		}
+
+		let added = palette.len() - self.palette.len();
+		self.transaction(|map| {
+			map.set_palette(palette);
+			if map.layer(TERRAIN_LAYER).is_some_and(|x| x.kind() != LayerKind::U16) {
+				map.remove_layer(TERRAIN_LAYER);
+			}
+			if map.layer(TERRAIN_LAYER).is_none() {
+				map.add_layer(TERRAIN_LAYER, LayerKind::U16)?;
+			}
+			map.set_layer_data(TERRAIN_LAYER, LayerData::U16(indices))?;
+			Ok(added)
+		})
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn striped_map() -> Map {
+		let mut map = Map::filled(6, 4, &GREEN).unwrap();
+		for hor in 0..6 {
+			map.set_at_mx(&MxPos::new(hor, 1), &BLUE).unwrap();
+		}
+		map.paint(&MxPos::new(2, 3), &Paint::Color([200, 190, 140, 255])).unwrap();
+		map.clear_history();
+		map
+	}
+
+	#[test]
+	fn builds_a_terrain_for_every_colour() {
+		let mut map = striped_map();
+		assert_eq!(map.build_palette_from_colors().unwrap(), 3);
+
+		for mx in map.positions() {
+			let terrain = map.terrain_at_mx(&mx).unwrap();
+			assert_eq!(Some(terrain.color()), map.get_at_mx(&mx));
+		}
+		// running it again finds nothing new
+		assert_eq!(map.build_palette_from_colors().unwrap(), 0);
+	}
+
+	#[test]
+	fn palette_migration_is_one_undo_step() {
+		for existing in [None, Some(LayerKind::U16), Some(LayerKind::U8)] {
+			let mut map = striped_map();
+			if let Some(kind) = existing {
+				map.add_layer(TERRAIN_LAYER, kind).unwrap();
+			}
+			let original = map.content_hash().unwrap();
+			let steps = map.history().undo_len();
+
+			map.build_palette_from_colors().unwrap();
+			let migrated = map.content_hash().unwrap();
+			assert_ne!(migrated, original);
+			assert_eq!(map.history().undo_len(), steps + 1);
+
+			assert!(map.undo());
+			assert_eq!(map.content_hash().unwrap(), original, "{:?}", existing);
+			assert!(map.redo());
+			assert_eq!(map.content_hash().unwrap(), migrated, "{:?}", existing);
+		}
+	}
+}
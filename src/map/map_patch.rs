@@ -0,0 +1,406 @@
+use std::{fs};
+use serde::*;
+use macroquad::prelude::*;
+use crate::position::*;
+use super::*;
+
+
+// the difference between a base map and a target map, applying it to the base recreates the target
+// cells are listed by [hor, ver] with the new values in a parallel list to keep the file compact
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MapPatch {
+	pub base: [i16; 2],
+	pub size: [i16; 2],
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub cells: Vec<[u16; 2]>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub colors: Vec<[u8; 4]>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub removed_layers: Vec<String>,
+	// only set when layers were added, removed or reordered
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub layer_order: Vec<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub header: Option<MapHeader>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub palette: Option<Palette>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub objects: Option<Vec<MapObject>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cell_data: Option<Vec<CellData>>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub layers: Vec<LayerPatch>,
+}
+
+// a layer with a different kind than the base layer of the same name replaces it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerPatch {
+	pub name: String,
+	pub cells: Vec<[u16; 2]>,
+	#[serde(flatten)]
+	pub data: LayerData,
+}
+
+impl MapPatch {
+	pub fn read_from_file(file_path: &str) -> Result<MapPatch, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))
+	}
+
+	pub fn write_to_file(&self, file_path: &str) -> Result<(), MapError> {
+		let toml = toml::to_string(self)
+			.map_err(|x| MapError::SerializeFailed(x.to_string()))?;
+
+		write_atomic(file_path, toml.as_bytes())
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.base == self.size
+			&& self.cells.is_empty()
+			&& self.removed_layers.is_empty()
+			&& self.layer_order.is_empty()
+			&& self.header.is_none()
+			&& self.palette.is_none()
+			&& self.objects.is_none()
+			&& self.cell_data.is_none()
+			&& self.layers.is_empty()
+	}
+
+	// the number of changed cell values, colours and layers combined
+	pub fn len(&self) -> usize {
+		self.cells.len() + self.layers.iter().map(|x| x.cells.len()).sum::<usize>()
+	}
+}
+
+
+impl Map {
+	pub fn diff(&self, target: &Map) -> MapPatch {
+		let mut patch = MapPatch {
+			base: [self.width, self.height],
+			size: [target.width, target.height],
+			..MapPatch::default()
+		};
+
+		// NOTE: cells that do not exist in the base are compared against what resizing adds
+		let blank = MapValue::from(&GRAY).color;
+		let width = target.width.max(0) as usize;
+
+		for (idx, value) in target.matrix.iter().enumerate() {
+			let mx = MxPos::new((idx % width) as i32, (idx / width) as i32);
+			let old = self.mx_index(&mx).map(|x| self.matrix[x].color).unwrap_or(blank);
+			if old != value.color {
+				patch.cells.push([mx.hor as u16, mx.ver as u16]);
+				patch.colors.push(value.color);
+			}
+		}
+
+		let mut restructured = false;
+		for layer in &self.layers {
+			if target.layer(&layer.name).is_none() {
+				patch.removed_layers.push(layer.name.clone());
+				restructured = true;
+			}
+		}
+
+		for layer in &target.layers {
+			let base = self.layer(&layer.name).filter(|x| x.kind() == layer.kind());
+			restructured |= base.is_none();
+
+			let mut cells = Vec::new();
+			let mut values = Vec::new();
+			for idx in 0..layer.data.len() {
+				let mx = MxPos::new((idx % width) as i32, (idx / width) as i32);
+				let old = base
+					.and_then(|x| x.data.get(self.mx_index(&mx)?))
+					.unwrap_or(layer.kind().default_value());
+				let new = layer.data.get(idx).unwrap_or(old);
+				if old != new {
+					cells.push([mx.hor as u16, mx.ver as u16]);
+					values.push(new);
+				}
+			}
+
+			if !cells.is_empty() || base.is_none() {
+				let mut data = LayerData::new(layer.kind(), values.len());
+				for (idx, value) in values.into_iter().enumerate() {
+					data.set(idx, value);
+				}
+				patch.layers.push(LayerPatch { name: layer.name.clone(), cells, data });
+			}
+		}
+
+		let names = |map: &Map| map.layers.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+		if restructured || names(self) != names(target) {
+			patch.layer_order = names(target);
+		}
+
+		if self.header != target.header {
+			patch.header = Some(target.header.clone());
+		}
+		if self.palette != target.palette {
+			patch.palette = Some(target.palette.clone());
+		}
+		if self.objects != target.objects {
+			patch.objects = Some(target.objects.clone());
+		}
+		if self.cell_data != target.cell_data {
+			patch.cell_data = Some(target.cell_data.clone());
+		}
+		patch
+	}
+
+	// the patch is checked against the size it ends up with before anything changes, a resize is
+	// applied first and clears the undo history like Map::resize does, all other changes become
+	// a single undo step
+	pub fn apply_patch(&mut self, patch: &MapPatch) -> Result<(), MapError> {
+		if patch.base != [self.width, self.height] {
+			return Err(MapError::PatchMismatch(format!(
+				"patch expects a {}x{} map, got {}x{}",
+				patch.base[0], patch.base[1], self.width, self.height
+			)));
+		}
+		if patch.cells.len() != patch.colors.len() {
+			return Err(MapError::PatchMismatch(format!(
+				"patch lists {} cells but {} colours", patch.cells.len(), patch.colors.len()
+			)));
+		}
+		if let Some(layer) = patch.layers.iter().find(|x| x.cells.len() != x.data.len()) {
+			return Err(MapError::PatchMismatch(format!(
+				"layer '{}' lists {} cells but {} values", layer.name, layer.cells.len(), layer.data.len()
+			)));
+		}
+
+		// NOTE: cells outside of the new size are the only part that can fail once the map is resized
+		let (width, height) = (patch.size[0].max(0) as u16, patch.size[1].max(0) as u16);
+		let mut cells = patch.cells.iter().chain(patch.layers.iter().flat_map(|x| &x.cells));
+		if let Some(pos) = cells.find(|x| x[0] >= width || x[1] >= height) {
+			return Err(MapError::PatchMismatch(format!(
+				"cell [{}, {}] lies outside of the {}x{} patched map", pos[0], pos[1], width, height
+			)));
+		}
+
+		if patch.size != patch.base {
+			self.resize(patch.size[0].max(0) as usize, patch.size[1].max(0) as usize, Anchor::TopLeft)?;
+		}
+
+		self.transaction(|map| {
+			if let Some(palette) = &patch.palette {
+				map.set_palette(palette.clone());
+			}
+
+			for name in &patch.removed_layers {
+				map.remove_layer(name);
+			}
+
+			for (pos, color) in patch.cells.iter().zip(&patch.colors) {
+				map.paint_cell(&MxPos::new(pos[0] as i32, pos[1] as i32), &Paint::Color(*color))?;
+			}
+
+			for layer in &patch.layers {
+				let kind = layer.data.kind();
+				if map.layer(&layer.name).is_some_and(|x| x.kind() != kind) {
+					map.remove_layer(&layer.name);
+				}
+				if map.layer(&layer.name).is_none() {
+					map.add_layer(&layer.name, kind)?;
+				}
+
+				for (idx, pos) in layer.cells.iter().enumerate() {
+					if let Some(value) = layer.data.get(idx) {
+						let paint = Paint::Layer(layer.name.clone(), value);
+						map.paint_cell(&MxPos::new(pos[0] as i32, pos[1] as i32), &paint)?;
+					}
+				}
+			}
+
+			if let Some(header) = &patch.header {
+				map.header = header.clone();
+			}
+			if let Some(objects) = &patch.objects {
+				map.objects = objects.clone();
+			}
+			if let Some(cell_data) = &patch.cell_data {
+				map.cell_data = cell_data.clone();
+				map.sort_cell_data();
+			}
+
+			// NOTE: reordering, the header, objects and cell data are not recorded, undoing only restores which layers exist
+			if !patch.layer_order.is_empty() {
+				map.layers.sort_by_key(|x| patch.layer_order.iter().position(|name| *name == x.name));
+			}
+			Ok(())
+		})
+	}
+
+	pub fn apply_patch_file(&mut self, file_path: &str) -> Result<(), MapError> {
+		self.apply_patch(&MapPatch::read_from_file(file_path)?)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn copy(map: &Map) -> Map {
+		Map::from_bytes(&map.to_bytes().unwrap()).unwrap()
+	}
+
+	fn base_map() -> Map {
+		let mut map = Map::filled(8, 6, &GREEN).unwrap();
+		map.fill_hex_range(&MxPos::new(3, 3), 2, &Paint::Color([30, 70, 160, 255])).unwrap();
+		map.set_palette({
+			let mut palette = Palette::new();
+			palette.define(TerrainType::new("grass", [60, 160, 60, 255])).unwrap();
+			palette.define(TerrainType::new("sand", [200, 190, 140, 255])).unwrap();
+			palette
+		});
+		map.add_layer(TERRAIN_LAYER, LayerKind::U16).unwrap();
+		map.add_layer("height", LayerKind::F32).unwrap();
+		map.add_layer("fog", LayerKind::Bool).unwrap();
+		map.draw_line(&MxPos::new(0, 0), &MxPos::new(7, 5), &Paint::Terrain("sand".to_string())).unwrap();
+		map.set_layer_at_mx("height", &MxPos::new(2, 2), LayerValue::F32(0.5)).unwrap();
+		map.add_object(MapObject::new("door", &MxPos::new(1, 4))).unwrap();
+		map.set_cell_property(&MxPos::new(5, 1), "gold", 3).unwrap();
+		map.clear_history();
+		map
+	}
+
+	// applying the diff to `base` gives `target`, also when the patch went through a file,
+	// `name` keeps the files of tests running at the same time apart
+	fn assert_patches(name: &str, base: &Map, target: &Map) {
+		let patch = base.diff(target);
+		let mut applied = copy(base);
+		applied.apply_patch(&patch).unwrap();
+		assert_eq!(applied.to_bytes().unwrap(), target.to_bytes().unwrap());
+
+		let path = std::env::temp_dir()
+			.join(format!("perspective_patch_{}_{}.toml", name, std::process::id()))
+			.to_string_lossy()
+			.to_string();
+		patch.write_to_file(&path).unwrap();
+		let read_back = MapPatch::read_from_file(&path);
+		fs::remove_file(&path).unwrap();
+		let read_back = read_back.unwrap();
+		assert_eq!(read_back, patch);
+
+		let mut applied = copy(base);
+		applied.apply_patch(&read_back).unwrap();
+		assert_eq!(applied.to_bytes().unwrap(), target.to_bytes().unwrap());
+	}
+
+	fn edit(map: &mut Map) {
+		map.paint(&MxPos::new(0, 0), &Paint::Color([1, 2, 3, 255])).unwrap();
+		map.set_terrain_at_mx(&MxPos::new(4, 1), "grass").unwrap();
+		map.set_layer_at_mx("height", &MxPos::new(1, 1), LayerValue::F32(0.25)).unwrap();
+		map.header_mut().name = "edited".to_string();
+		map.set_cell_property(&MxPos::new(2, 3), "note", "hello").unwrap();
+	}
+
+	#[test]
+	fn identical_maps_have_an_empty_patch() {
+		let map = base_map();
+		assert!(map.diff(&copy(&map)).is_empty());
+		assert_patches("identical", &map, &copy(&map));
+	}
+
+	#[test]
+	fn patches_cells_and_extras() {
+		let base = base_map();
+		let mut target = copy(&base);
+		edit(&mut target);
+		target.add_object(MapObject::new("chest", &MxPos::new(6, 5))).unwrap();
+
+		assert_patches("extras", &base, &target);
+		assert_patches("extras", &target, &base);
+	}
+
+	#[test]
+	fn patches_added_removed_and_changed_layers() {
+		let base = base_map();
+		let mut target = copy(&base);
+		target.remove_layer("fog");
+		target.remove_layer("height");
+		target.add_layer("height", LayerKind::U8).unwrap();
+		target.set_layer_at_mx("height", &MxPos::new(3, 3), LayerValue::U8(7)).unwrap();
+		target.add_layer("moisture", LayerKind::F32).unwrap();
+		target.add_layer("blank", LayerKind::Color).unwrap();
+
+		assert_patches("layers", &base, &target);
+		assert_patches("layers", &target, &base);
+	}
+
+	#[test]
+	fn patches_reordered_layers() {
+		let base = base_map();
+		let mut target = copy(&base);
+		let terrain = target.remove_layer(TERRAIN_LAYER).unwrap();
+		target.layers.push(terrain);
+
+		assert_patches("order", &base, &target);
+	}
+
+	#[test]
+	fn patches_size_changes() {
+		let base = base_map();
+		for (width, height, anchor) in [(11, 9, Anchor::Center), (5, 4, Anchor::BottomRight), (8, 10, Anchor::Top), (6, 4, Anchor::Left)] {
+			let mut target = copy(&base);
+			target.resize(width, height, anchor).unwrap();
+			edit(&mut target);
+			target.remove_layer("fog");
+			target.add_layer("moisture", LayerKind::U8).unwrap();
+			target.set_layer_at_mx("moisture", &MxPos::new(width as i32 - 1, height as i32 - 1), LayerValue::U8(9)).unwrap();
+
+			assert_patches("resized", &base, &target);
+			assert_patches("resized", &target, &base);
+		}
+	}
+
+	#[test]
+	fn patches_between_unrelated_maps() {
+		let base = base_map();
+		let mut target = Map::filled(4, 13, &BLUE).unwrap();
+		target.add_layer("fog", LayerKind::Bool).unwrap();
+		target.set_layer_at_mx("fog", &MxPos::new(2, 12), LayerValue::Bool(true)).unwrap();
+
+		assert_patches("unrelated", &base, &target);
+		assert_patches("unrelated", &target, &base);
+	}
+
+	#[test]
+	fn rejects_patches_for_another_base() {
+		let base = base_map();
+		let mut target = copy(&base);
+		target.resize(9, 6, Anchor::TopLeft).unwrap();
+		let patch = base.diff(&target);
+
+		let mut other = Map::filled(7, 6, &GREEN).unwrap();
+		assert!(matches!(other.apply_patch(&patch), Err(MapError::PatchMismatch(_))));
+	}
+
+	#[test]
+	fn failing_patches_leave_the_map_alone() {
+		let mut base = base_map();
+		base.paint(&MxPos::new(1, 1), &Paint::Color([9, 9, 9, 255])).unwrap();
+		let mut target = copy(&base);
+		target.resize(5, 4, Anchor::TopLeft).unwrap();
+		edit(&mut target);
+
+		let mut patch = base.diff(&target);
+		patch.cells.push([4, 4]);
+		patch.colors.push([1, 1, 1, 255]);
+		let before = base.to_bytes().unwrap();
+		assert!(matches!(base.apply_patch(&patch), Err(MapError::PatchMismatch(_))));
+		assert_eq!(base.to_bytes().unwrap(), before);
+		assert_eq!(base.history().undo_len(), 1);
+
+		let mut patch = base.diff(&target);
+		patch.layers[0].cells[0] = [5, 0];
+		assert!(matches!(base.apply_patch(&patch), Err(MapError::PatchMismatch(_))));
+		assert_eq!(base.to_bytes().unwrap(), before);
+		assert_eq!(base.history().undo_len(), 1);
+	}
+}
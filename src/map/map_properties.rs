@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::*;
+use serde::de::DeserializeOwned;
+use crate::position::*;
+use super::*;
+
+
+// free-form values and tags attached to a single cell, for triggers, quest markers and the like
+// NOTE: only cells that hold something are stored, sorted by row and then by column
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CellData {
+	pub pos: [u16; 2],
+	#[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+	pub tags: BTreeSet<String>,
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub properties: BTreeMap<String, toml::Value>,
+}
+
+impl CellData {
+	pub fn new(mx: &MxPos) -> Self {
+		CellData {
+			pos: [mx.hor.max(0) as u16, mx.ver.max(0) as u16],
+			tags: BTreeSet::new(),
+			properties: BTreeMap::new(),
+		}
+	}
+
+	pub fn mx(&self) -> MxPos {
+		MxPos::new(self.pos[0] as i32, self.pos[1] as i32)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.tags.is_empty() && self.properties.is_empty()
+	}
+
+	fn sort_key(&self) -> (u16, u16) {
+		(self.pos[1], self.pos[0])
+	}
+}
+
+
+// reads follow the edge mode like get_at_mx does, writes only wrap
+// NOTE: cell data is not drawn, but every change to it is recorded in the history like painting
+impl Map {
+	pub fn cell_data(&self) -> &Vec<CellData> {
+		&self.cell_data
+	}
+
+	pub fn cell_data_at_mx(&self, mx: &MxPos) -> Option<&CellData> {
+		let mx = self.resolve_mx(mx)?;
+		self.cell_data_slot(&mx).ok().map(|x| &self.cell_data[x])
+	}
+
+	pub fn cell_property(&self, mx: &MxPos, key: &str) -> Option<&toml::Value> {
+		self.cell_data_at_mx(mx)?.properties.get(key)
+	}
+
+	// None when the property is missing or holds a value of another type
+	pub fn cell_property_as<T: DeserializeOwned>(&self, mx: &MxPos, key: &str) -> Option<T> {
+		self.cell_property(mx, key)?.clone().try_into().ok()
+	}
+
+	// returns the value that was replaced
+	pub fn set_cell_property<V: Into<toml::Value>>(&mut self, mx: &MxPos, key: &str, value: V) -> Result<Option<toml::Value>, MapError> {
+		let value = value.into();
+		self.edit_cell_data(mx, true, |x| x.properties.insert(key.to_string(), value))
+			.map(|x| x.flatten())
+	}
+
+	pub fn remove_cell_property(&mut self, mx: &MxPos, key: &str) -> Option<toml::Value> {
+		self.edit_cell_data(mx, false, |x| x.properties.remove(key)).ok().flatten().flatten()
+	}
+
+	pub fn has_tag(&self, mx: &MxPos, tag: &str) -> bool {
+		self.cell_data_at_mx(mx).is_some_and(|x| x.tags.contains(tag))
+	}
+
+	// returns whether the cell did not have the tag yet
+	pub fn tag_cell(&mut self, mx: &MxPos, tag: &str) -> Result<bool, MapError> {
+		self.edit_cell_data(mx, true, |x| x.tags.insert(tag.to_string()))
+			.map(|x| x.unwrap_or(false))
+	}
+
+	// returns whether the cell had the tag
+	pub fn untag_cell(&mut self, mx: &MxPos, tag: &str) -> bool {
+		self.edit_cell_data(mx, false, |x| x.tags.remove(tag)).is_ok_and(|x| x.unwrap_or(false))
+	}
+
+	pub fn cells_tagged(&self, tag: &str) -> Vec<MxPos> {
+		self.cell_data.iter()
+			.filter(|x| x.tags.contains(tag))
+			.map(|x| x.mx())
+			.collect()
+	}
+
+	pub fn cells_with_property(&self, key: &str) -> Vec<(MxPos, &toml::Value)> {
+		self.cell_data.iter()
+			.filter_map(|x| x.properties.get(key).map(|value| (x.mx(), value)))
+			.collect()
+	}
+
+	pub fn clear_cell_data(&mut self, mx: &MxPos) -> Option<CellData> {
+		let mx = self.wrap_mx(mx);
+		let old = self.cell_data[self.cell_data_slot(&mx).ok()?].clone();
+		self.record(MapChange::CellData { pos: old.pos, old: Some(old.clone()), new: None });
+		self.restore_cell_data(&mx, None);
+		Some(old)
+	}
+
+	// puts hand edited entries back in order, entries for the same cell are merged into the first
+	pub(super) fn sort_cell_data(&mut self) {
+		self.cell_data.sort_by_key(|x| x.sort_key());
+
+		let mut merged: Vec<CellData> = Vec::with_capacity(self.cell_data.len());
+		for data in self.cell_data.drain(..) {
+			match merged.last_mut().filter(|x| x.pos == data.pos) {
+				Some(last) => {
+					last.tags.extend(data.tags);
+					for (key, value) in data.properties {
+						last.properties.entry(key).or_insert(value);
+					}
+				},
+				None => merged.push(data),
+			}
+		}
+		self.cell_data = merged;
+	}
+
+	// Err is where a new entry belongs, positions outside of the map are never found
+	fn cell_data_slot(&self, mx: &MxPos) -> Result<usize, usize> {
+		if self.mx_index(mx).is_none() { return Err(0); }
+
+		let key = (mx.ver.max(0) as u16, mx.hor.max(0) as u16);
+		self.cell_data.binary_search_by_key(&key, |x| x.sort_key())
+	}
+
+	// edits a copy of the entry of a cell and records the difference, a missing entry is only
+	// created when `create` is set and entries that end up empty are dropped
+	fn edit_cell_data<T>(&mut self, mx: &MxPos, create: bool, edit: impl FnOnce(&mut CellData) -> T) -> Result<Option<T>, MapError> {
+		let mx = self.wrap_mx(mx);
+		if self.mx_index(&mx).is_none() {
+			return Err(MapError::out_of_bounds("mx", &format!("{:?}", mx), &format!("{}x{}", self.width, self.height)));
+		}
+
+		let old = self.cell_data_slot(&mx).ok().map(|x| self.cell_data[x].clone());
+		let mut data = match (&old, create) {
+			(Some(old), _) => old.clone(),
+			(None, true) => CellData::new(&mx),
+			(None, false) => return Ok(None),
+		};
+		let result = edit(&mut data);
+
+		let new = (!data.is_empty()).then_some(data);
+		if new != old {
+			self.record(MapChange::CellData { pos: [mx.hor as u16, mx.ver as u16], old, new: new.clone() });
+			self.restore_cell_data(&mx, new);
+		}
+		Ok(Some(result))
+	}
+
+	// puts an entry in place or removes it, bypassing the history
+	pub(super) fn restore_cell_data(&mut self, mx: &MxPos, data: Option<CellData>) {
+		self.revision += 1;
+		match (self.cell_data_slot(mx), data) {
+			(Ok(slot), Some(data)) => self.cell_data[slot] = data,
+			(Ok(slot), None) => { self.cell_data.remove(slot); },
+			(Err(slot), Some(data)) => self.cell_data.insert(slot, data),
+			(Err(_), None) => {},
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tags_round_trip() {
+		let mut map = Map::filled(6, 4, &GRAY).unwrap();
+		let (a, b) = (MxPos::new(1, 1), MxPos::new(4, 3));
+
+		assert!(map.tag_cell(&a, "spawn").unwrap());
+		assert!(!map.tag_cell(&a, "spawn").unwrap());
+		assert!(map.tag_cell(&b, "spawn").unwrap());
+		assert!(map.tag_cell(&b, "exit").unwrap());
+		assert!(map.has_tag(&a, "spawn"));
+		assert_eq!(map.cells_tagged("spawn"), vec![a.clone(), b.clone()]);
+		assert_eq!(map.cells_tagged("exit"), vec![b.clone()]);
+
+		assert!(map.untag_cell(&a, "spawn"));
+		assert!(!map.untag_cell(&a, "spawn"));
+		assert!(!map.untag_cell(&MxPos::new(0, 0), "spawn"));
+		assert!(!map.has_tag(&a, "spawn"));
+		// an entry without tags or properties is dropped
+		assert!(map.cell_data_at_mx(&a).is_none());
+		assert_eq!(map.cell_data().len(), 1);
+
+		assert!(map.tag_cell(&MxPos::new(6, 0), "spawn").is_err());
+		assert!(map.cells_tagged("missing").is_empty());
+	}
+
+	#[test]
+	fn properties_keep_their_types() {
+		let mut map = Map::filled(6, 4, &GRAY).unwrap();
+		let mx = MxPos::new(2, 3);
+
+		assert_eq!(map.set_cell_property(&mx, "gold", 12).unwrap(), None);
+		assert_eq!(map.set_cell_property(&mx, "gold", 15).unwrap(), Some(toml::Value::Integer(12)));
+		map.set_cell_property(&mx, "note", "behind the well").unwrap();
+		assert_eq!(map.cell_property_as::<i64>(&mx, "gold"), Some(15));
+		assert_eq!(map.cell_property_as::<String>(&mx, "gold"), None);
+		assert_eq!(map.cell_property_as::<String>(&mx, "note").as_deref(), Some("behind the well"));
+		assert_eq!(map.cells_with_property("gold"), vec![(mx.clone(), &toml::Value::Integer(15))]);
+
+		assert_eq!(map.remove_cell_property(&mx, "gold"), Some(toml::Value::Integer(15)));
+		assert_eq!(map.remove_cell_property(&mx, "gold"), None);
+		assert_eq!(map.clear_cell_data(&mx).unwrap().properties.len(), 1);
+		assert!(map.cell_data().is_empty());
+	}
+
+	#[test]
+	fn edits_are_recorded_in_the_history() {
+		let mut map = Map::filled(6, 4, &GRAY).unwrap();
+		let mx = MxPos::new(3, 2);
+		let revision = map.revision();
+
+		map.tag_cell(&mx, "treasure").unwrap();
+		map.set_cell_property(&mx, "gold", 5).unwrap();
+		map.untag_cell(&mx, "treasure");
+		map.clear_cell_data(&mx);
+		assert!(map.revision() > revision);
+		assert_eq!(map.history().undo_len(), 4);
+		// edits that change nothing add no step
+		map.untag_cell(&mx, "treasure");
+		map.remove_cell_property(&mx, "gold");
+		assert_eq!(map.history().undo_len(), 4);
+
+		assert!(map.undo());
+		assert_eq!(map.cell_property_as::<i64>(&mx, "gold"), Some(5));
+		assert!(!map.has_tag(&mx, "treasure"));
+		assert!(map.undo());
+		assert!(map.has_tag(&mx, "treasure"));
+		assert!(map.undo());
+		assert!(map.undo());
+		assert!(map.cell_data().is_empty());
+
+		assert!(map.redo());
+		assert!(map.redo());
+		assert_eq!(map.cell_data_at_mx(&mx).unwrap().tags.len(), 1);
+		assert_eq!(map.cell_property_as::<i64>(&mx, "gold"), Some(5));
+
+		// a transaction of cell edits is one step
+		map.transaction(|map| {
+			map.tag_cell(&MxPos::new(0, 0), "a")?;
+			map.tag_cell(&MxPos::new(1, 0), "b")
+		}).unwrap();
+		assert!(map.undo());
+		assert!(map.cells_tagged("a").is_empty() && map.cells_tagged("b").is_empty());
+	}
+
+	#[test]
+	fn cell_data_survives_saving() {
+		let mut map = Map::filled(6, 4, &Color::from_rgba(10, 20, 30, 255)).unwrap();
+		map.tag_cell(&MxPos::new(5, 3), "exit").unwrap();
+		map.tag_cell(&MxPos::new(0, 1), "spawn").unwrap();
+		map.set_cell_property(&MxPos::new(0, 1), "facing", "north").unwrap();
+		let mut chest = toml::map::Map::new();
+		chest.insert("gold".to_string(), toml::Value::Integer(30));
+		chest.insert("locked".to_string(), toml::Value::Boolean(true));
+		map.set_cell_property(&MxPos::new(2, 2), "chest", toml::Value::Table(chest)).unwrap();
+
+		for extension in ["toml", "pmap"] {
+			let path = std::env::temp_dir()
+				.join(format!("perspective_cell_data_{}.{}", std::process::id(), extension))
+				.to_string_lossy()
+				.to_string();
+			map.write_to_file(&path).unwrap();
+			let read_back = Map::read_from_file(&path);
+			std::fs::remove_file(&path).unwrap();
+			let read_back = read_back.unwrap();
+
+			assert_eq!(read_back.cell_data(), map.cell_data());
+			assert_eq!(read_back.cells_tagged("spawn"), vec![MxPos::new(0, 1)]);
+			assert_eq!(read_back.cell_property(&MxPos::new(2, 2), "chest").and_then(|x| x.get("gold")), Some(&toml::Value::Integer(30)));
+		}
+	}
+}
@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, VecDeque};
+use crate::position::*;
+use super::*;
+
+
+// the smallest rectangle of matrix positions holding a set of cells, both corners included
+// NOTE: a region that wraps across a seam spans the whole width or height of the map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MxBounds {
+	pub min: MxPos,
+	pub max: MxPos,
+}
+
+impl MxBounds {
+	pub fn of<'a>(cells: impl IntoIterator<Item = &'a MxPos>) -> Option<MxBounds> {
+		let mut cells = cells.into_iter();
+		let first = cells.next()?;
+		let mut bounds = MxBounds { min: first.clone(), max: first.clone() };
+
+		for mx in cells {
+			bounds.min = MxPos::new(bounds.min.hor.min(mx.hor), bounds.min.ver.min(mx.ver));
+			bounds.max = MxPos::new(bounds.max.hor.max(mx.hor), bounds.max.ver.max(mx.ver));
+		}
+		Some(bounds)
+	}
+
+	pub fn width(&self) -> i32 {
+		self.max.hor - self.min.hor + 1
+	}
+
+	pub fn height(&self) -> i32 {
+		self.max.ver - self.min.ver + 1
+	}
+
+	pub fn contains(&self, mx: &MxPos) -> bool {
+		mx.hor >= self.min.hor && mx.hor <= self.max.hor
+		&& mx.ver >= self.min.ver && mx.ver <= self.max.ver
+	}
+}
+
+
+// a group of connected cells, cells are listed in the order they were reached
+#[derive(Debug, Clone)]
+pub struct Region {
+	pub id: usize,
+	pub cells: Vec<MxPos>,
+	pub bounds: MxBounds,
+}
+
+impl Region {
+	pub fn size(&self) -> usize {
+		self.cells.len()
+	}
+}
+
+
+// the result of labeling, regions are numbered in row order of their first cell
+#[derive(Debug, Clone)]
+pub struct RegionMap {
+	width: i16,
+	height: i16,
+	labels: Vec<Option<usize>>,
+	regions: Vec<Region>,
+}
+
+impl RegionMap {
+	pub fn regions(&self) -> &Vec<Region> {
+		&self.regions
+	}
+
+	pub fn len(&self) -> usize {
+		self.regions.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.regions.is_empty()
+	}
+
+	pub fn label_at(&self, mx: &MxPos) -> Option<usize> {
+		if mx.hor < 0 || mx.hor >= self.width as i32 || mx.ver < 0 || mx.ver >= self.height as i32 {
+			return None;
+		}
+		self.labels[mx.ver as usize * self.width as usize + mx.hor as usize]
+	}
+
+	pub fn region_at(&self, mx: &MxPos) -> Option<&Region> {
+		self.regions.get(self.label_at(mx)?)
+	}
+
+	// the first of the largest regions
+	pub fn largest(&self) -> Option<&Region> {
+		self.regions.iter().rev().max_by_key(|x| x.size())
+	}
+
+	pub fn connected(&self, a: &MxPos, b: &MxPos) -> bool {
+		self.label_at(a).is_some() && self.label_at(a) == self.label_at(b)
+	}
+}
+
+
+// read only questions about a whole map, for generators and balance checks
+impl Map {
+	// every position of the map in row order
+	pub fn positions(&self) -> impl Iterator<Item = MxPos> + '_ {
+		let width = self.width.max(0) as i32;
+		(0..self.matrix.len()).map(move |idx| MxPos::new(idx as i32 % width, idx as i32 / width))
+	}
+
+	pub fn cells_where<'a>(&'a self, predicate: impl Fn(&Map, &MxPos) -> bool + 'a) -> impl Iterator<Item = MxPos> + 'a {
+		self.positions().filter(move |mx| predicate(self, mx))
+	}
+
+	pub fn count_where(&self, predicate: impl Fn(&Map, &MxPos) -> bool) -> usize {
+		self.cells_where(predicate).count()
+	}
+
+	pub fn bounds_where(&self, predicate: impl Fn(&Map, &MxPos) -> bool) -> Option<MxBounds> {
+		MxBounds::of(&self.cells_where(predicate).collect::<Vec<_>>())
+	}
+
+	// number of cells per raw cell colour
+	pub fn color_counts(&self) -> BTreeMap<[u8; 4], usize> {
+		let mut counts = BTreeMap::new();
+		for value in &self.matrix {
+			*counts.entry(value.color).or_insert(0) += 1;
+		}
+		counts
+	}
+
+	// number of cells per terrain name, cells without a terrain are not counted
+	pub fn terrain_counts(&self) -> BTreeMap<String, usize> {
+		let mut counts = BTreeMap::new();
+		for mx in self.positions() {
+			if let Some(terrain) = self.terrain_at_mx(&mx) {
+				*counts.entry(terrain.name.clone()).or_insert(0) += 1;
+			}
+		}
+		counts
+	}
+
+	// connected groups of cells the predicate holds for, like islands or rooms
+	pub fn label_regions(&self, predicate: impl Fn(&Map, &MxPos) -> bool) -> RegionMap {
+		self.label_regions_by(|map, mx| predicate(map, mx).then_some(()))
+	}
+
+	// connected groups of cells with the same key, cells without a key belong to no region
+	// NOTE: cells connect across seams when the map wraps, see EdgeMode
+	pub fn label_regions_by<K: PartialEq>(&self, key: impl Fn(&Map, &MxPos) -> Option<K>) -> RegionMap {
+		let width = self.width.max(0) as usize;
+		let keys: Vec<Option<K>> = self.positions().map(|mx| key(self, &mx)).collect();
+		let mut labels: Vec<Option<usize>> = vec![None; keys.len()];
+		let mut regions = Vec::new();
+
+		for (start, start_key) in keys.iter().enumerate() {
+			if start_key.is_none() || labels[start].is_some() { continue; }
+
+			let id = regions.len();
+			let mut cells = Vec::new();
+			let mut queue = VecDeque::from([start]);
+			labels[start] = Some(id);
+
+			while let Some(idx) = queue.pop_front() {
+				let mx = MxPos::new((idx % width) as i32, (idx / width) as i32);
+				for next in self.neighbours_at_mx(&mx) {
+					let next_idx = match self.mx_index(&next) {
+						Some(next_idx) => next_idx,
+						None => continue,
+					};
+					if labels[next_idx].is_none() && keys[next_idx] == *start_key {
+						labels[next_idx] = Some(id);
+						queue.push_back(next_idx);
+					}
+				}
+				cells.push(mx);
+			}
+
+			let bounds = MxBounds::of(&cells).unwrap();
+			regions.push(Region { id, cells, bounds });
+		}
+
+		RegionMap { width: self.width.max(0), height: self.height.max(0), labels, regions }
+	}
+
+	// regions of the same terrain, or of the same raw colour for cells without one
+	pub fn terrain_regions(&self) -> RegionMap {
+		self.label_regions_by(|map, mx| Some(match map.terrain_index_at_mx(mx) {
+			Some(terrain) => Ok(terrain),
+			None => Err(map.matrix[map.mx_index(mx)?].color),
+		}))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn land() -> Color {
+		Color::from_rgba(60, 160, 60, 255)
+	}
+
+	fn water() -> Color {
+		Color::from_rgba(30, 80, 200, 255)
+	}
+
+	// water with land on the listed cells
+	fn islands(width: usize, height: usize, cells: &[(i32, i32)]) -> Map {
+		let mut map = Map::filled(width, height, &water()).unwrap();
+		for (hor, ver) in cells {
+			map.set_at_mx(&MxPos::new(*hor, *ver), &land()).unwrap();
+		}
+		map
+	}
+
+	fn is_land(map: &Map, mx: &MxPos) -> bool {
+		map.get_at_mx(mx) == Some(land())
+	}
+
+	fn sorted(mut cells: Vec<MxPos>) -> Vec<MxPos> {
+		cells.sort_by_key(|x| (x.ver, x.hor));
+		cells
+	}
+
+	#[test]
+	fn counts_islands_on_a_hand_made_map() {
+		// odd rows lean right, so (2, 1) touches (3, 2) but (2, 0) does not touch (3, 1)
+		let map = islands(8, 6, &[(2, 0), (3, 1), (2, 1), (3, 2), (6, 0), (7, 0), (0, 4), (0, 5), (5, 4)]);
+		let regions = map.label_regions(is_land);
+
+		assert_eq!(regions.len(), 4);
+		let sizes: Vec<usize> = regions.regions().iter().map(|x| x.size()).collect();
+		assert_eq!(sizes, vec![4, 2, 2, 1]);
+		assert!(regions.connected(&MxPos::new(2, 0), &MxPos::new(3, 2)));
+		assert!(!regions.connected(&MxPos::new(2, 0), &MxPos::new(6, 0)));
+		assert!(!regions.connected(&MxPos::new(1, 1), &MxPos::new(1, 1)));
+		assert_eq!(regions.label_at(&MxPos::new(5, 4)), Some(3));
+		assert_eq!(regions.label_at(&MxPos::new(4, 4)), None);
+		assert_eq!(regions.label_at(&MxPos::new(-1, 0)), None);
+		assert_eq!(regions.largest().unwrap().id, 0);
+		assert_eq!(regions.regions()[0].bounds, MxBounds { min: MxPos::new(2, 0), max: MxPos::new(3, 2) });
+
+		// the same diagonal step joins cells leaving an odd row but not cells leaving an even one
+		assert_eq!(islands(8, 6, &[(2, 1), (3, 2)]).label_regions(is_land).len(), 1);
+		assert_eq!(islands(8, 6, &[(2, 2), (3, 3)]).label_regions(is_land).len(), 2);
+	}
+
+	#[test]
+	fn regions_join_across_seams() {
+		let mut map = islands(6, 4, &[(0, 1), (5, 1), (2, 3)]);
+		assert_eq!(map.label_regions(is_land).len(), 3);
+
+		map.set_edge_mode(EdgeMode::WrapHorizontal);
+		let regions = map.label_regions(is_land);
+		assert_eq!(regions.len(), 2);
+		assert_eq!(regions.largest().unwrap().bounds.width(), 6);
+	}
+
+	#[test]
+	fn cells_in_a_radius_on_even_and_odd_rows() {
+		let map = Map::filled(6, 6, &water()).unwrap();
+		let even = MxPos::new(2, 2);
+		let odd = MxPos::new(2, 3);
+
+		let near_even = sorted(map.cells_where(|_, mx| mx.hex_distance(&even) <= 1).collect());
+		assert_eq!(near_even, vec![
+			MxPos::new(1, 1), MxPos::new(2, 1),
+			MxPos::new(1, 2), MxPos::new(2, 2), MxPos::new(3, 2),
+			MxPos::new(1, 3), MxPos::new(2, 3),
+		]);
+		let near_odd = sorted(map.cells_where(|_, mx| mx.hex_distance(&odd) <= 1).collect());
+		assert_eq!(near_odd, vec![
+			MxPos::new(2, 2), MxPos::new(3, 2),
+			MxPos::new(1, 3), MxPos::new(2, 3), MxPos::new(3, 3),
+			MxPos::new(2, 4), MxPos::new(3, 4),
+		]);
+
+		for center in [even.clone(), odd.clone()] {
+			for radius in 0..3 {
+				let found = sorted(map.cells_where(|_, mx| mx.hex_distance(&center) <= radius).collect());
+				assert_eq!(found, sorted(center.range(radius)));
+				assert_eq!(found.len() as i32, 3 * radius * (radius + 1) + 1);
+			}
+		}
+		assert_eq!(map.bounds_where(|_, mx| mx.hex_distance(&odd) <= 2), Some(MxBounds { min: MxPos::new(0, 1), max: MxPos::new(4, 5) }));
+		assert_eq!(map.bounds_where(|_, mx| mx.hor > 9), None);
+	}
+
+	#[test]
+	fn counts_colours_and_terrains() {
+		let mut map = islands(5, 4, &[(0, 0), (1, 0), (4, 3)]);
+		assert_eq!(map.count_where(is_land), 3);
+		assert_eq!(map.color_counts().get(&[60, 160, 60, 255]), Some(&3));
+		assert_eq!(map.color_counts().get(&[30, 80, 200, 255]), Some(&17));
+		assert!(map.terrain_counts().is_empty());
+
+		// without terrain the two colours make the regions, with it the terrain does
+		assert_eq!(map.terrain_regions().len(), 3);
+		let mut palette = Palette::new();
+		palette.define(TerrainType::new("sea", [30, 80, 200, 255])).unwrap();
+		palette.define(TerrainType::new("reef", [30, 80, 200, 255])).unwrap();
+		map.set_palette(palette);
+		map.fill_rect(&MxPos::new(0, 0), &MxPos::new(4, 3), &Paint::Terrain("sea".to_string())).unwrap();
+		map.set_terrain_at_mx(&MxPos::new(2, 2), "reef").unwrap();
+
+		assert_eq!(map.terrain_counts().get("sea"), Some(&19));
+		assert_eq!(map.terrain_counts().get("reef"), Some(&1));
+		assert_eq!(map.terrain_regions().len(), 2);
+	}
+}
@@ -6,11 +6,27 @@ use crate::*;
 
 impl Map {
 	pub fn read_from_file(file_path: &str) -> Result<Map, MapError> {
-			let toml = fs::read_to_string(file_path)
-				.map_err(|_x| MapError::ReadFileFailed)?;
+			Map::read_from_file_with(file_path, LoadMode::Strict)
+	}
+
+	pub fn read_from_file_with(file_path: &str, mode: LoadMode) -> Result<Map, MapError> {
+			let bytes = fs::read(file_path)
+				.map_err(|x| MapError::read_failed(file_path, x))?;
+
+			let map = match MapFormat::detect(&bytes) {
+				MapFormat::Binary => Map::from_bytes(&bytes).map_err(|x| x.at_path(file_path))?,
+				MapFormat::Toml => Map::from_toml(file_path, &bytes)?,
+			};
+			map.check(file_path, mode)
+	}
+
+	fn from_toml(file_path: &str, bytes: &[u8]) -> Result<Map, MapError> {
+			let toml = std::str::from_utf8(bytes)
+				.map_err(|x| MapError::parse_failed(file_path, &x.to_string()))?;
 
+			let toml = Map::migrate(file_path, toml)?;
 			let map: Map = toml::from_str(&toml)
-				.map_err(|_x| MapError::ParseFileFailed)?;
+				.map_err(|x| MapError::toml_failed(file_path, &toml, &x))?;
 			
 			Ok(map)
 	}
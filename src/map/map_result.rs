@@ -1,22 +1,148 @@
+use std::{fmt, io};
+use super::{MapIssue, LayerKind};
+
+
+const MAX_LISTED_ISSUES: usize = 20;
+
 
 #[derive(Debug)]
 pub enum MapError {
 	GenericError,
-	ReadFileFailed,
-	ParseFileFailed,
-	WriteFileFailed,
+	ReadFileFailed { path: String, source: io::Error },
+	ParseFileFailed { path: String, message: String, location: Option<FileLocation> },
+	InvalidMap { path: String, issues: Vec<MapIssue> },
+	UnsupportedVersion { path: String, found: i64, supported: u32 },
+	SerializeFailed(String),
+	WriteFileFailed { path: String, source: io::Error },
 
 	OutOfBounds(String),
+	UnknownLayer(String),
+	LayerExists(String),
+	LayerKindMismatch { layer: String, expected: LayerKind, found: LayerKind },
+	UnknownTerrain(String),
+	PatchMismatch(String),
+	InvalidConfig(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileLocation {
+	pub line: usize,
+	pub column: usize,
+	pub snippet: String,
+}
+
+impl FileLocation {
+	pub fn from_offset(source: &str, offset: usize) -> FileLocation {
+		let offset = offset.min(source.len());
+		let before = &source[..offset];
+		let line_start = before.rfind('\n').map(|x| x + 1).unwrap_or(0);
+		let line_end = source[offset..].find('\n').map(|x| x + offset).unwrap_or(source.len());
+
+		FileLocation {
+			line: before.matches('\n').count() + 1,
+			column: before[line_start..].chars().count() + 1,
+			snippet: source[line_start..line_end].trim_end().to_string(),
+		}
+	}
+
+	// for parsers that report a 1 based line and column instead of an offset
+	pub fn from_line(source: &str, line: usize, column: usize) -> FileLocation {
+		FileLocation {
+			line,
+			column,
+			snippet: source.lines().nth(line.saturating_sub(1)).unwrap_or("").trim_end().to_string(),
+		}
+	}
 }
 
 impl MapError {
 	pub fn out_of_bounds(param: &str, value: &str, expected: &str) -> MapError {
 		MapError::OutOfBounds(
-			format!("Parameter '{}' has a value of '{}' which is too large! Value may not exceed {}", 
-				param, 
-				value, 
+			format!("Parameter '{}' has a value of '{}' which is too large! Value may not exceed {}",
+				param,
+				value,
 				expected
 			)
 		)
 	}
+
+	pub fn read_failed(path: &str, source: io::Error) -> MapError {
+		MapError::ReadFileFailed { path: path.to_string(), source }
+	}
+
+	pub fn write_failed(path: &str, source: io::Error) -> MapError {
+		MapError::WriteFileFailed { path: path.to_string(), source }
+	}
+
+	pub fn parse_failed(path: &str, message: &str) -> MapError {
+		MapError::ParseFileFailed { path: path.to_string(), message: message.to_string(), location: None }
+	}
+
+	pub fn toml_failed(path: &str, source: &str, err: &toml::de::Error) -> MapError {
+		MapError::ParseFileFailed {
+			path: path.to_string(),
+			message: err.message().to_string(),
+			location: err.span().map(|x| FileLocation::from_offset(source, x.start)),
+		}
+	}
+
+	// binary decoding has no notion of a path, the reader fills it in afterwards
+	pub fn at_path(self, file_path: &str) -> MapError {
+		match self {
+			MapError::ParseFileFailed { message, location, .. } =>
+				MapError::ParseFileFailed { path: file_path.to_string(), message, location },
+			MapError::UnsupportedVersion { found, supported, .. } =>
+				MapError::UnsupportedVersion { path: file_path.to_string(), found, supported },
+			other => other,
+		}
+	}
+}
+
+impl fmt::Display for MapError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MapError::GenericError => write!(f, "unknown map error"),
+			MapError::ReadFileFailed { path, source } =>
+				write!(f, "could not read map file '{}' ({:?}): {}", path, source.kind(), source),
+			MapError::ParseFileFailed { path, message, location: Some(loc) } =>
+				write!(f, "could not parse map file '{}' at line {}, column {}: {}\n  | {}\n  | {:>col$}",
+					path, loc.line, loc.column, message.trim_end(), loc.snippet, "^", col = loc.column),
+			MapError::ParseFileFailed { path, message, location: None } =>
+				write!(f, "could not parse map file '{}': {}", path, message.trim_end()),
+			MapError::InvalidMap { path, issues } => {
+				write!(f, "map file '{}' has {} integrity issue(s):", path, issues.len())?;
+				for issue in issues.iter().take(MAX_LISTED_ISSUES) {
+					write!(f, "\n  - {}", issue)?;
+				}
+				if issues.len() > MAX_LISTED_ISSUES {
+					write!(f, "\n  ... and {} more", issues.len() - MAX_LISTED_ISSUES)?;
+				}
+				Ok(())
+			},
+			MapError::UnsupportedVersion { path, found, supported } =>
+				write!(f, "map file '{}' has format version {}, only versions up to {} are supported", path, found, supported),
+			MapError::SerializeFailed(message) =>
+				write!(f, "could not serialize map: {}", message),
+			MapError::WriteFileFailed { path, source } =>
+				write!(f, "could not write map file '{}' ({:?}): {}", path, source.kind(), source),
+			MapError::OutOfBounds(message) => write!(f, "{}", message),
+			MapError::UnknownLayer(name) => write!(f, "map has no layer named '{}'", name),
+			MapError::LayerExists(name) => write!(f, "map already has a layer named '{}'", name),
+			MapError::LayerKindMismatch { layer, expected, found } =>
+				write!(f, "layer '{}' holds {} values, got a {} value", layer, expected, found),
+			MapError::UnknownTerrain(name) => write!(f, "palette has no terrain named '{}'", name),
+			MapError::PatchMismatch(message) => write!(f, "could not apply map patch: {}", message),
+			MapError::InvalidConfig(message) => write!(f, "invalid generator config: {}", message),
+		}
+	}
+}
+
+impl std::error::Error for MapError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			MapError::ReadFileFailed { source, .. } => Some(source),
+			MapError::WriteFileFailed { source, .. } => Some(source),
+			_ => None,
+		}
+	}
 }
@@ -0,0 +1,183 @@
+use std::{fs, io};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use super::*;
+
+
+// writes to a temporary file next to the target and renames it over the target once it is
+// synced to disk, so a crash mid-save leaves either the old or the new file, never half of one
+pub fn write_atomic(file_path: &str, bytes: &[u8]) -> Result<(), MapError> {
+	write_atomic_with_backups(file_path, bytes, 0)
+}
+
+// keeps up to `backups` earlier versions as `<file>.1.bak` (newest) to `<file>.<n>.bak` (oldest)
+pub fn write_atomic_with_backups(file_path: &str, bytes: &[u8], backups: usize) -> Result<(), MapError> {
+	let path = Path::new(file_path);
+	let temp = sibling(path, ".tmp");
+
+	let result = write_synced(&temp, bytes)
+		.and_then(|_| rotate_backups(path, backups))
+		.and_then(|_| fs::rename(&temp, path));
+
+	if let Err(err) = result {
+		let _ = fs::remove_file(&temp);
+		return Err(MapError::write_failed(file_path, err));
+	}
+
+	// NOTE: makes the rename itself durable, not every platform can open a directory for this
+	if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+		let _ = fs::File::open(dir).and_then(|x| x.sync_all());
+	}
+	Ok(())
+}
+
+pub fn backup_path(file_path: &str, number: usize) -> PathBuf {
+	sibling(Path::new(file_path), &format!(".{}.bak", number))
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(suffix);
+	path.with_file_name(name)
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> io::Result<()> {
+	let mut file = fs::File::create(path)?;
+	file.write_all(bytes)?;
+	file.sync_all()
+}
+
+// the current file is linked (or copied) into the first backup, it stays in place until the rename
+fn rotate_backups(path: &Path, backups: usize) -> io::Result<()> {
+	if backups == 0 || !path.exists() { return Ok(()); }
+
+	let file_path = path.to_string_lossy();
+	for number in (1..backups).rev() {
+		let from = backup_path(&file_path, number);
+		if from.exists() {
+			fs::rename(from, backup_path(&file_path, number + 1))?;
+		}
+	}
+
+	let first = backup_path(&file_path, 1);
+	if first.exists() {
+		fs::remove_file(&first)?;
+	}
+	fs::hard_link(path, &first).or_else(|_x| fs::copy(path, &first).map(|_| ()))
+}
+
+
+impl Map {
+	pub fn write_to_file_with_backups(&self, file_path: &str, backups: usize) -> Result<(), MapError> {
+		let bytes = self.encode(MapFormat::from_path(file_path))?;
+		write_atomic_with_backups(file_path, &bytes, backups)
+	}
+}
+
+
+// saves a map every `interval`, but only when it changed since the last save
+#[derive(Debug)]
+pub struct Autosave {
+	path: String,
+	interval: Duration,
+	backups: usize,
+	last_save: Instant,
+	saved_revision: u64,
+}
+
+impl Autosave {
+	// `map` is taken as already saved, so nothing is written until it changes
+	pub fn new(file_path: &str, map: &Map, interval: Duration) -> Self {
+		Autosave {
+			path: file_path.to_string(),
+			interval,
+			backups: 0,
+			last_save: Instant::now(),
+			saved_revision: map.revision(),
+		}
+	}
+
+	pub fn with_backups(mut self, backups: usize) -> Self {
+		self.backups = backups;
+		self
+	}
+
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	// call once per frame, returns whether the map was written
+	pub fn tick(&mut self, map: &Map) -> Result<bool, MapError> {
+		if self.last_save.elapsed() < self.interval || map.revision() == self.saved_revision {
+			return Ok(false);
+		}
+		self.save_now(map)?;
+		Ok(true)
+	}
+
+	// NOTE: a failed save is retried on the next interval, not on the next frame
+	pub fn save_now(&mut self, map: &Map) -> Result<(), MapError> {
+		self.last_save = Instant::now();
+		map.write_to_file_with_backups(&self.path, self.backups)?;
+		self.saved_revision = map.revision();
+		Ok(())
+	}
+
+	// for when the saved map is replaced, e.g. after loading another file
+	pub fn reset(&mut self, map: &Map) {
+		self.saved_revision = map.revision();
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::position::*;
+
+	fn temp_path(name: &str) -> String {
+		std::env::temp_dir()
+			.join(format!("perspective_{}_{}.pmap", name, std::process::id()))
+			.to_string_lossy()
+			.to_string()
+	}
+
+	#[test]
+	fn autosave_skips_unchanged_maps() {
+		let path = temp_path("autosave_unchanged");
+		let mut map = Map::filled(4, 4, &GREEN).unwrap();
+		map.paint(&MxPos::new(1, 1), &Paint::Color([1, 2, 3, 255])).unwrap();
+		map.write_to_file(&path).unwrap();
+
+		let mut autosave = Autosave::new(&path, &map, Duration::ZERO).with_backups(2);
+		assert!(!autosave.tick(&map).unwrap());
+		assert!(!Path::new(&backup_path(&path, 1)).exists());
+
+		map.paint(&MxPos::new(2, 2), &Paint::Color([4, 5, 6, 255])).unwrap();
+		assert!(autosave.tick(&map).unwrap());
+		assert!(!autosave.tick(&map).unwrap());
+		assert!(Path::new(&backup_path(&path, 1)).exists());
+		assert!(!Path::new(&backup_path(&path, 2)).exists());
+
+		let saved = Map::read_from_file(&path).unwrap();
+		assert_eq!(saved.to_bytes().unwrap(), map.to_bytes().unwrap());
+		fs::remove_file(&path).unwrap();
+		fs::remove_file(backup_path(&path, 1)).unwrap();
+	}
+
+	#[test]
+	fn autosave_waits_for_the_interval() {
+		let path = temp_path("autosave_interval");
+		let mut map = Map::filled(4, 4, &GREEN).unwrap();
+		let mut autosave = Autosave::new(&path, &map, Duration::from_secs(3600));
+
+		map.paint(&MxPos::new(1, 1), &Paint::Color([1, 2, 3, 255])).unwrap();
+		assert!(!autosave.tick(&map).unwrap());
+		assert!(!Path::new(&path).exists());
+
+		autosave.save_now(&map).unwrap();
+		assert!(Path::new(&path).exists());
+		fs::remove_file(&path).unwrap();
+	}
+}
@@ -0,0 +1,34 @@
+use macroquad::prelude::*;
+use crate::position::*;
+use super::*;
+
+
+// anything the scene can pull tile colours and heights from
+pub trait TileSource {
+	fn tile_color(&mut self, mx: &MxPos) -> Result<Option<Color>, MapError>;
+	fn tile_elevation(&mut self, mx: &MxPos) -> Result<f32, MapError>;
+	// the cell a tile position shows, differs from the position itself when the source wraps
+	fn tile_cell(&self, mx: &MxPos) -> MxPos {
+		mx.clone()
+	}
+	// cells that changed since the last call
+	fn take_dirty(&mut self) -> DirtyCells;
+}
+
+impl TileSource for Map {
+	fn tile_color(&mut self, mx: &MxPos) -> Result<Option<Color>, MapError> {
+		Ok(self.terrain_color_at_mx(mx))
+	}
+
+	fn tile_elevation(&mut self, mx: &MxPos) -> Result<f32, MapError> {
+		Ok(self.elevation_at_mx(mx))
+	}
+
+	fn tile_cell(&self, mx: &MxPos) -> MxPos {
+		self.resolve_mx(mx).unwrap_or_else(|| mx.clone())
+	}
+
+	fn take_dirty(&mut self) -> DirtyCells {
+		Map::take_dirty(self)
+	}
+}
@@ -0,0 +1,354 @@
+use macroquad::prelude::*;
+use crate::position::*;
+use super::*;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+	TopLeft,
+	Top,
+	TopRight,
+	Left,
+	Center,
+	Right,
+	BottomLeft,
+	Bottom,
+	BottomRight,
+}
+
+impl Anchor {
+	// how much of the size difference goes before the old content, per axis
+	fn factors(&self) -> (i32, i32) {
+		match self {
+			Anchor::TopLeft => (0, 0),
+			Anchor::Top => (1, 0),
+			Anchor::TopRight => (2, 0),
+			Anchor::Left => (0, 1),
+			Anchor::Center => (1, 1),
+			Anchor::Right => (2, 1),
+			Anchor::BottomLeft => (0, 2),
+			Anchor::Bottom => (1, 2),
+			Anchor::BottomRight => (2, 2),
+		}
+	}
+}
+
+
+// rotation in 60 degree steps clockwise around the center cell of the stamp,
+// mirroring (left to right) is applied before rotating
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StampTransform {
+	pub rotation: u8,
+	pub mirror: bool,
+}
+
+impl StampTransform {
+	pub fn new(rotation: u8, mirror: bool) -> Self {
+		StampTransform { rotation: rotation % 6, mirror }
+	}
+
+	pub fn apply(&self, cube: CubePos) -> CubePos {
+		let mut cube = match self.mirror {
+			true => cube.mirror(),
+			false => cube,
+		};
+		for _ in 0..self.rotation % 6 {
+			cube = cube.rotate_cw();
+		}
+		cube
+	}
+}
+
+
+impl Map {
+	// copies a width x height block starting at `from` into a new map with the same palette,
+	// objects and cell data on the copied cells come along
+	// NOTE: the block is moved as a hex shape, when `from` is on an odd row every other row
+	// of it starts one column further right, so the cells keep their neighbours
+	pub fn extract(&self, from: &MxPos, width: usize, height: usize) -> Result<Map, MapError> {
+		let mut sub = Map::filled(width, height, &BLANK)?;
+		sub.palette = self.palette.clone();
+		for layer in &self.layers {
+			sub.layers.push(MapLayer::new(&layer.name, layer.kind(), sub.matrix.len()));
+		}
+
+		for idx in 0..sub.matrix.len() {
+			let local = MxPos::new((idx % width) as i32, (idx / width) as i32);
+			let mx = from.hex_translate(&local);
+			if let Some(src) = self.mx_index(&mx) {
+				sub.copy_cell_from(self, src, idx);
+
+				if let Some(data) = self.cell_data_at_mx(&mx) {
+					sub.cell_data.push(CellData { pos: [local.hor as u16, local.ver as u16], ..data.clone() });
+				}
+			}
+
+			for object in self.objects.iter().filter(|x| x.mx() == mx) {
+				sub.objects.push(MapObject { pos: [local.hor as u16, local.ver as u16], ..object.clone() });
+			}
+		}
+		Ok(sub)
+	}
+
+	// writes the stamp with its top left cell at `at` as a single undo step, terrains are
+	// matched by name and added to the palette when missing, transparent cells are skipped
+	// NOTE: the stamp is moved and turned as a hex shape around its center cell, like `extract`
+	// an untransformed stamp on an odd row has every other row one column further right,
+	// cells past a wrapping edge come out on the other side, objects and cell data are not copied
+	pub fn stamp(&mut self, stamp: &Map, at: &MxPos, transform: StampTransform) -> Result<usize, MapError> {
+		let width = stamp.width.max(0) as i32;
+		let pivot = CubePos::from(MxPos::new(width / 2, stamp.height.max(0) as i32 / 2));
+		let origin = CubePos::from(at) + pivot;
+
+		self.transaction(|map| {
+			let mut changed = 0;
+
+			for (idx, value) in stamp.matrix.iter().enumerate() {
+				if value.color[3] == 0 { continue; }
+
+				let local = CubePos::from(MxPos::new(idx as i32 % width, idx as i32 / width));
+				let target = MxPos::from(origin + transform.apply(local - pivot));
+				if map.write_index(&target).is_none() { continue; }
+
+				let mut cell_changed = map.paint_cell(&target, &Paint::Color(value.color))?;
+
+				for layer in &stamp.layers {
+					let value = match layer.data.get(idx) {
+						Some(value) => value,
+						None => continue,
+					};
+
+					let paint = match (layer.name == TERRAIN_LAYER, value) {
+						(true, LayerValue::U16(terrain)) => match stamp.palette.get(terrain) {
+							Some(terrain) => {
+								if map.palette.index_of(&terrain.name).is_none() {
+									let mut palette = map.palette.clone();
+									palette.define(terrain.clone())?;
+									map.set_palette(palette);
+								}
+								Paint::Terrain(terrain.name.clone())
+							},
+							None => continue,
+						},
+						_ => {
+							if map.layer(&layer.name).is_none() {
+								map.add_layer(&layer.name, layer.kind())?;
+							}
+							Paint::Layer(layer.name.clone(), value)
+						},
+					};
+					cell_changed |= map.paint_cell(&target, &paint)?;
+				}
+
+				if cell_changed {
+					changed += 1;
+				}
+			}
+			Ok(changed)
+		})
+	}
+
+	// grows or shrinks the map around the anchor, new cells are gray and hold layer defaults
+	// NOTE: the undo history refers to cell indices, so it is cleared by resizing
+	pub fn resize(&mut self, width: usize, height: usize, anchor: Anchor) -> Result<(), MapError> {
+		let mut resized = Map::filled(width, height, &GRAY)?;
+		for layer in &self.layers {
+			resized.layers.push(MapLayer::new(&layer.name, layer.kind(), resized.matrix.len()));
+		}
+
+		let (fx, fy) = anchor.factors();
+		let offset = MxPos::new(
+			(width as i32 - self.width as i32) * fx / 2,
+			(height as i32 - self.height as i32) * fy / 2,
+		);
+
+		let old_width = self.width.max(0) as i32;
+		for idx in 0..self.matrix.len() {
+			let old = MxPos::new(idx as i32 % old_width, idx as i32 / old_width);
+			if let Some(dst) = resized.mx_index(&old.hex_translate(&offset)) {
+				resized.copy_cell_from(self, idx, dst);
+			}
+		}
+
+		self.objects.retain_mut(|object| {
+			let mx = object.mx().hex_translate(&offset);
+			object.pos = [mx.hor.max(0) as u16, mx.ver.max(0) as u16];
+			resized.mx_index(&mx).is_some()
+		});
+		self.cell_data.retain_mut(|data| {
+			let mx = data.mx().hex_translate(&offset);
+			data.pos = [mx.hor.max(0) as u16, mx.ver.max(0) as u16];
+			resized.mx_index(&mx).is_some()
+		});
+		self.header.spawn_points.retain_mut(|spawn| {
+			let mx = spawn.mx().hex_translate(&offset);
+			spawn.pos = [mx.hor.max(0) as u16, mx.ver.max(0) as u16];
+			resized.mx_index(&mx).is_some()
+		});
+
+		self.width = resized.width;
+		self.height = resized.height;
+		self.matrix = resized.matrix;
+		self.layers = resized.layers;
+		self.clear_history();
+		self.mark_all_dirty();
+		Ok(())
+	}
+
+	// copies colour and every layer the maps have in common, bypassing the history
+	fn copy_cell_from(&mut self, other: &Map, src: usize, dst: usize) {
+		self.matrix[dst].color = other.matrix[src].color;
+
+		for layer in &mut self.layers {
+			if let Some(value) = other.layer(&layer.name).and_then(|x| x.data.get(src)) {
+				layer.data.set(dst, value);
+			}
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// a colour that survives being stored as bytes
+	fn ground() -> Color {
+		Color::from_rgba(130, 130, 130, 255)
+	}
+
+	// a stamp whose cells all have a different colour and a layer value
+	fn numbered(width: usize, height: usize) -> Map {
+		let mut map = Map::filled(width, height, &BLANK).unwrap();
+		map.add_layer("height", LayerKind::U8).unwrap();
+		for idx in 0..width * height {
+			let mx = MxPos::new((idx % width) as i32, (idx / width) as i32);
+			map.set_at_mx(&mx, &Color::from_rgba(10 + idx as u8, 200, 100, 255)).unwrap();
+			map.set_layer_at_mx("height", &mx, LayerValue::U8(idx as u8 + 1)).unwrap();
+		}
+		map
+	}
+
+	fn local(stamp: &Map, idx: usize) -> MxPos {
+		MxPos::new(idx as i32 % stamp.width as i32, idx as i32 / stamp.width as i32)
+	}
+
+	// where a stamp cell has to end up, worked out independently of `stamp`
+	fn expected_target(stamp: &Map, at: &MxPos, idx: usize, transform: StampTransform) -> MxPos {
+		let pivot = MxPos::new(stamp.width as i32 / 2, stamp.height as i32 / 2);
+		let from_pivot = CubePos::from(local(stamp, idx)) - CubePos::from(&pivot);
+		let turned = transform.apply(from_pivot);
+		MxPos::from(CubePos::from(at) + CubePos::from(&pivot) + turned)
+	}
+
+	fn assert_same_cells(a: &Map, b: &Map) {
+		assert_eq!((a.width, a.height), (b.width, b.height));
+		for idx in 0..a.matrix.len() {
+			assert_eq!(a.matrix[idx].color, b.matrix[idx].color, "cell {}", idx);
+			assert_eq!(a.get_layer_at_mx("height", &local(a, idx)), b.get_layer_at_mx("height", &local(b, idx)), "cell {}", idx);
+		}
+	}
+
+	#[test]
+	fn extract_and_stamp_round_trip_on_even_and_odd_rows() {
+		let stamp = numbered(4, 3);
+		for at in [MxPos::new(2, 2), MxPos::new(3, 3), MxPos::new(0, 0), MxPos::new(5, 5)] {
+			let mut map = Map::filled(12, 10, &ground()).unwrap();
+			assert_eq!(map.stamp(&stamp, &at, StampTransform::default()).unwrap(), 12);
+			assert_same_cells(&map.extract(&at, 4, 3).unwrap(), &stamp);
+
+			for idx in 0..stamp.matrix.len() {
+				let target = expected_target(&stamp, &at, idx, StampTransform::default());
+				assert_eq!(target, at.hex_translate(&local(&stamp, idx)));
+				assert_eq!(map.get_at_mx(&target), Some(stamp.matrix[idx].color()));
+			}
+		}
+	}
+
+	#[test]
+	fn odd_rows_shift_every_other_row() {
+		let stamp = numbered(3, 2);
+		let mut map = Map::filled(8, 8, &ground()).unwrap();
+		map.stamp(&stamp, &MxPos::new(2, 3), StampTransform::default()).unwrap();
+
+		// the top row lands on row 3 from column 2, the second row on row 4 from column 3
+		assert_eq!(map.get_at_mx(&MxPos::new(2, 3)), Some(stamp.matrix[0].color()));
+		assert_eq!(map.get_at_mx(&MxPos::new(3, 4)), Some(stamp.matrix[3].color()));
+		assert_eq!(map.get_at_mx(&MxPos::new(2, 4)), Some(ground()));
+	}
+
+	#[test]
+	fn every_transform_keeps_the_shape() {
+		let stamp = numbered(3, 3);
+		let at = MxPos::new(5, 5);
+		for mirror in [false, true] {
+		for rotation in 0..6 {
+			let transform = StampTransform::new(rotation, mirror);
+			let mut map = Map::filled(14, 14, &ground()).unwrap();
+			assert_eq!(map.stamp(&stamp, &at, transform).unwrap(), 9);
+
+			let targets: Vec<MxPos> = (0..stamp.matrix.len())
+				.map(|idx| expected_target(&stamp, &at, idx, transform))
+				.collect();
+			for (idx, target) in targets.iter().enumerate() {
+				assert_eq!(map.get_at_mx(target), Some(stamp.matrix[idx].color()), "{:?} cell {}", transform, idx);
+				assert_eq!(map.get_layer_at_mx("height", target), Some(LayerValue::U8(idx as u8 + 1)));
+			}
+
+			// the center stays put and cells next to each other in the stamp still are
+			assert_eq!(targets[4], at.hex_translate(&MxPos::new(1, 1)));
+			for a in 0..targets.len() {
+			for b in 0..targets.len() {
+				let before = local(&stamp, a).hex_distance(&local(&stamp, b));
+				assert_eq!(targets[a].hex_distance(&targets[b]), before, "{:?}", transform);
+			}}
+		}}
+	}
+
+	#[test]
+	fn transforms_are_distinct_and_rotations_cycle() {
+		let stamp = numbered(3, 3);
+		let at = MxPos::new(4, 4);
+		let mut seen = Vec::new();
+		for mirror in [false, true] {
+		for rotation in 0..6 {
+			let mut map = Map::filled(12, 12, &ground()).unwrap();
+			map.stamp(&stamp, &at, StampTransform::new(rotation, mirror)).unwrap();
+			let bytes = map.to_bytes().unwrap();
+			assert!(!seen.contains(&bytes), "rotation {} mirror {}", rotation, mirror);
+			seen.push(bytes);
+		}}
+
+		assert_eq!(StampTransform::new(6, false), StampTransform::default());
+		let cube = CubePos::new(2, -1);
+		assert_eq!(StampTransform::new(3, false).apply(StampTransform::new(3, false).apply(cube)), cube);
+	}
+
+	#[test]
+	fn stamps_wrap_across_seams() {
+		let stamp = numbered(3, 2);
+		let at = MxPos::new(6, 2);
+
+		let mut void = Map::filled(8, 6, &ground()).unwrap();
+		assert_eq!(void.stamp(&stamp, &at, StampTransform::default()).unwrap(), 4);
+		assert_eq!(void.get_at_mx(&MxPos::new(0, 2)), Some(ground()));
+
+		let mut wrapped = Map::filled(8, 6, &ground()).unwrap();
+		wrapped.set_edge_mode(EdgeMode::WrapHorizontal);
+		assert_eq!(wrapped.stamp(&stamp, &at, StampTransform::default()).unwrap(), 6);
+		assert_eq!(wrapped.get_at_mx(&MxPos::new(0, 2)), Some(stamp.matrix[2].color()));
+		// `at` is on an even row, so the second row starts at column 6 as well
+		assert_eq!(wrapped.get_at_mx(&MxPos::new(6, 3)), Some(stamp.matrix[3].color()));
+		assert_eq!(wrapped.get_at_mx(&MxPos::new(0, 3)), Some(stamp.matrix[5].color()));
+	}
+
+	#[test]
+	fn stamping_is_one_undo_step() {
+		let stamp = numbered(3, 3);
+		let mut map = Map::filled(8, 8, &ground()).unwrap();
+		let before = map.to_bytes().unwrap();
+		map.stamp(&stamp, &MxPos::new(1, 1), StampTransform::new(2, true)).unwrap();
+		assert!(map.undo());
+		assert_eq!(map.to_bytes().unwrap(), before);
+	}
+}
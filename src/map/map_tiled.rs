@@ -0,0 +1,873 @@
+use std::{fs};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use serde::*;
+use macroquad::prelude::*;
+use crate::position::*;
+use super::*;
+
+
+// tiled stores horizontal, vertical, diagonal and hexagonal rotation flips in the top bits of a gid
+const TILED_FLIP_FLAGS: u32 = 0xF000_0000;
+
+
+// what the importer understands of a tiled map, filled by both the xml and the json reader
+#[derive(Debug, Default)]
+struct TiledMap {
+	orientation: String,
+	infinite: bool,
+	width: u32,
+	height: u32,
+	tile_width: f32,
+	tile_height: f32,
+	side_length: f32,
+	stagger_x: bool,
+	stagger_even: bool,
+	tilesets: Vec<TiledTileset>,
+	layers: Vec<TiledLayer>,
+}
+
+#[derive(Debug, Default)]
+struct TiledTileset {
+	first_gid: u32,
+	name: String,
+	tiles: BTreeMap<u32, TiledTile>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct TiledTile {
+	class: String,
+	image: Option<String>,
+	properties: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug)]
+enum TiledLayer {
+	Tiles { name: String, gids: Vec<u32> },
+	Objects { name: String, objects: Vec<TiledObject> },
+}
+
+#[derive(Debug, Default)]
+struct TiledObject {
+	id: u32,
+	name: String,
+	class: String,
+	x: f32,
+	y: f32,
+	width: f32,
+	height: f32,
+	gid: Option<u32>,
+	properties: BTreeMap<String, toml::Value>,
+}
+
+
+impl Map {
+	// imports a staggered hexagonal map made with the Tiled editor from a .tmx (xml) or .tmj (json) file
+	// every used tile becomes a palette terrain named after its class, palette entry 0 is the empty
+	// tile, the layer named "terrain" (or else the first tile layer) becomes the terrain layer and the
+	// other tile layers become u16 layers of palette indices, object layers become map objects
+	// NOTE: tiled draws rows shifted by `staggerindex`, maps staggered on even rows get an extra
+	// empty row on top to line up with the odd row layout of the engine, flat topped maps
+	// (staggered on the x axis) are turned a quarter clockwise to become pointy topped
+	pub fn from_tiled(file_path: &str) -> Result<Map, MapError> {
+		let source = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+		let dir = Path::new(file_path).parent().unwrap_or(Path::new(""));
+
+		let tiled = match source.trim_start().starts_with('<') {
+			true => read_tmx(file_path, &source, dir)?,
+			false => read_tmj(file_path, &source, dir)?,
+		};
+		tiled.into_map(file_path)?.check(file_path, LoadMode::Strict)
+	}
+}
+
+
+impl TiledMap {
+	fn into_map(mut self, file_path: &str) -> Result<Map, MapError> {
+		if self.orientation != "hexagonal" {
+			return Err(MapError::parse_failed(file_path,
+				&format!("only hexagonal maps can be imported, found orientation '{}'", self.orientation)));
+		}
+		if self.infinite {
+			return Err(MapError::parse_failed(file_path, "infinite maps can not be imported"));
+		}
+
+		// one row more than the maximum is left for the row shift
+		if self.width >= i16::MAX as u32 || self.height >= i16::MAX as u32 {
+			return Err(MapError::parse_failed(file_path,
+				&format!("a {}x{} map is too large to import", self.width, self.height)));
+		}
+
+		let cells = self.width as usize * self.height as usize;
+		if let Some(name) = self.layers.iter().find_map(|x| match x {
+			TiledLayer::Tiles { name, gids } if gids.len() != cells => Some(name),
+			_ => None,
+		}) {
+			return Err(MapError::parse_failed(file_path,
+				&format!("layer '{}' does not hold {}x{} tiles", name, self.width, self.height)));
+		}
+
+		let (width, height) = match self.stagger_x {
+			true => (self.height, self.width + self.row_shift()),
+			false => (self.width, self.height + self.row_shift()),
+		};
+		let mut map = Map::filled(width as usize, height as usize, &BLANK)?;
+
+		self.tilesets.sort_by_key(|x| x.first_gid);
+		let terrains = self.build_palette(&mut map)?;
+
+		let terrain_layer = self.layers.iter()
+			.position(|x| matches!(x, TiledLayer::Tiles { name, .. } if name == TERRAIN_LAYER))
+			.or_else(|| self.layers.iter().position(|x| matches!(x, TiledLayer::Tiles { .. })));
+
+		for (i, layer) in self.layers.iter().enumerate() {
+			match layer {
+				TiledLayer::Tiles { name, gids } => {
+					let name = match Some(i) == terrain_layer { true => TERRAIN_LAYER, false => name };
+					let mut indices = vec![0u16; map.matrix.len()];
+
+					for (idx, gid) in gids.iter().enumerate() {
+						let mx = self.to_mx(idx as u32 % self.width, idx as u32 / self.width);
+						let terrain = terrains.get(&(gid & !TILED_FLIP_FLAGS)).copied().unwrap_or(0);
+						let cell = map.mx_index(&mx).unwrap();
+						indices[cell] = terrain;
+
+						if name == TERRAIN_LAYER {
+							map.matrix[cell].color = map.palette.get(terrain).unwrap().color;
+						}
+					}
+					map.layers.push(MapLayer { name: name.to_string(), data: LayerData::U16(indices) });
+				},
+				TiledLayer::Objects { name, objects } => {
+					for object in objects {
+						map.objects.push(self.to_object(name, object));
+					}
+				},
+			}
+		}
+		Ok(map)
+	}
+
+	fn build_palette(&self, map: &mut Map) -> Result<BTreeMap<u32, u16>, MapError> {
+		map.palette.define(TerrainType::new("void", [0, 0, 0, 0]))?;
+
+		let used: BTreeSet<u32> = self.layers.iter()
+			.flat_map(|x| match x {
+				TiledLayer::Tiles { gids, .. } => gids.as_slice(),
+				TiledLayer::Objects { .. } => &[],
+			})
+			.map(|x| x & !TILED_FLIP_FLAGS)
+			.filter(|x| *x != 0)
+			.collect();
+
+		// NOTE: tiles sharing a class share a terrain, the first tile defines it
+		let mut terrains = BTreeMap::new();
+		for gid in used {
+			let (tileset, tile) = self.tile(gid);
+			let name = match (tile.class.is_empty(), tileset.name.is_empty()) {
+				(false, _) => tile.class.clone(),
+				(true, false) => format!("{}_{}", tileset.name, gid - tileset.first_gid),
+				(true, true) => format!("tile_{}", gid),
+			};
+
+			let idx = match map.palette.index_of(&name) {
+				Some(idx) => idx,
+				None => map.palette.define(to_terrain(&name, gid, &tile))?,
+			};
+			terrains.insert(gid, idx);
+		}
+		Ok(terrains)
+	}
+
+	fn tile(&self, gid: u32) -> (&TiledTileset, TiledTile) {
+		static NO_TILESET: TiledTileset = TiledTileset { first_gid: 0, name: String::new(), tiles: BTreeMap::new() };
+
+		let tileset = self.tilesets.iter().rev()
+			.find(|x| x.first_gid <= gid)
+			.unwrap_or(&NO_TILESET);
+		let tile = tileset.tiles.get(&(gid - tileset.first_gid)).cloned().unwrap_or_default();
+		(tileset, tile)
+	}
+
+	fn row_shift(&self) -> u32 {
+		(self.stagger_x != self.stagger_even) as u32
+	}
+
+	fn to_mx(&self, col: u32, row: u32) -> MxPos {
+		match self.stagger_x {
+			true => MxPos::new((self.height - 1 - row) as i32, (col + self.row_shift()) as i32),
+			false => MxPos::new(col as i32, (row + self.row_shift()) as i32),
+		}
+	}
+
+	fn to_object(&self, layer: &str, object: &TiledObject) -> MapObject {
+		// tile objects are anchored at their bottom left corner, shapes at their top left
+		let (x, y) = match object.gid {
+			Some(_) => (object.x + object.width / 2.0, object.y - object.height / 2.0),
+			None => (object.x + object.width / 2.0, object.y + object.height / 2.0),
+		};
+		let (col, row) = self.cell_at(x, y);
+
+		let class = match (object.class.is_empty(), object.gid) {
+			(true, Some(gid)) => self.tile(gid & !TILED_FLIP_FLAGS).1.class,
+			_ => object.class.clone(),
+		};
+
+		MapObject {
+			name: match object.name.is_empty() {
+				true => format!("{}_{}", layer, object.id),
+				false => object.name.clone(),
+			},
+			kind: class,
+			group: layer.to_string(),
+			properties: object.properties.clone(),
+			..MapObject::new("", &self.to_mx(col, row))
+		}
+	}
+
+	// the tile whose center lies closest to a pixel position
+	fn cell_at(&self, x: f32, y: f32) -> (u32, u32) {
+		let (tw, th) = (self.tile_width.max(1.0), self.tile_height.max(1.0));
+		let staggered = |i: u32| (i % 2 == 1) != self.stagger_even;
+		let center = |col: u32, row: u32| match self.stagger_x {
+			true => (
+				col as f32 * (tw + self.side_length) / 2.0 + tw / 2.0,
+				row as f32 * th + th / 2.0 + staggered(col) as u8 as f32 * th / 2.0,
+			),
+			false => (
+				col as f32 * tw + tw / 2.0 + staggered(row) as u8 as f32 * tw / 2.0,
+				row as f32 * (th + self.side_length) / 2.0 + th / 2.0,
+			),
+		};
+
+		let (step_x, step_y) = match self.stagger_x {
+			true => ((tw + self.side_length) / 2.0, th),
+			false => (tw, (th + self.side_length) / 2.0),
+		};
+		let guess_col = (x / step_x).max(0.0) as u32;
+		let guess_row = (y / step_y).max(0.0) as u32;
+
+		let mut best = (0, 0, f32::MAX);
+		for row in guess_row.saturating_sub(1)..=guess_row + 1 {
+		for col in guess_col.saturating_sub(1)..=guess_col + 1 {
+			let (col, row) = (col.min(self.width.saturating_sub(1)), row.min(self.height.saturating_sub(1)));
+			let (cx, cy) = center(col, row);
+			let dist = (cx - x).powi(2) + (cy - y).powi(2);
+			if dist < best.2 {
+				best = (col, row, dist);
+			}
+		}}
+		(best.0, best.1)
+	}
+}
+
+// the well known properties fill the terrain fields, everything else is kept as is
+fn to_terrain(name: &str, gid: u32, tile: &TiledTile) -> TerrainType {
+	let mut properties = tile.properties.clone();
+	let color = properties.remove("color")
+		.and_then(|x| parse_color(x.as_str()?))
+		.unwrap_or_else(|| fallback_color(gid));
+
+	let mut terrain = TerrainType::new(name, color);
+	terrain.sprite = tile.image.clone();
+	if let Some(cost) = properties.remove("movement_cost").and_then(|x| as_f32(&x)) {
+		terrain.movement_cost = cost;
+	}
+	if let Some(opacity) = properties.remove("opacity").and_then(|x| as_f32(&x)) {
+		terrain.opacity = opacity;
+	}
+	terrain.properties = properties;
+	terrain
+}
+
+fn as_f32(value: &toml::Value) -> Option<f32> {
+	match value {
+		toml::Value::Float(x) => Some(*x as f32),
+		toml::Value::Integer(x) => Some(*x as f32),
+		_ => None,
+	}
+}
+
+// tiled writes colours as #AARRGGBB, or #RRGGBB when fully opaque
+fn parse_color(text: &str) -> Option<[u8; 4]> {
+	let hex = text.strip_prefix('#')?;
+	let value = u32::from_str_radix(hex, 16).ok()?;
+	let [a, r, g, b] = match hex.len() {
+		8 => value.to_be_bytes(),
+		6 => (value | 0xFF00_0000).to_be_bytes(),
+		_ => return None,
+	};
+	Some([r, g, b, a])
+}
+
+// tiles without a colour still need to be told apart on the map
+fn fallback_color(gid: u32) -> [u8; 4] {
+	let hash = gid.wrapping_mul(2_654_435_761).to_be_bytes();
+	[64 + hash[0] % 160, 64 + hash[1] % 160, 64 + hash[2] % 160, 255]
+}
+
+
+// tile data is either comma separated or little endian u32 values in (optionally compressed) base64
+fn decode_gids(text: &str, encoding: &str, compression: &str) -> Result<Vec<u32>, String> {
+	if encoding == "csv" {
+		return text.split(',')
+			.map(|x| x.trim())
+			.filter(|x| !x.is_empty())
+			.map(|x| x.parse::<u32>().map_err(|_e| format!("'{}' is not a tile id", x)))
+			.collect();
+	}
+	if encoding != "base64" {
+		return Err(format!("unsupported tile encoding '{}'", encoding));
+	}
+
+	let bytes = decode_base64(text).ok_or("tile data is not valid base64")?;
+	let bytes = match compression {
+		"" => bytes,
+		"zlib" => inflate(flate2::read::ZlibDecoder::new(bytes.as_slice()))?,
+		"gzip" => inflate(flate2::read::GzDecoder::new(bytes.as_slice()))?,
+		other => return Err(format!("unsupported tile compression '{}'", other)),
+	};
+
+	if bytes.len() % 4 != 0 {
+		return Err("tile data is not a whole number of tile ids".to_string());
+	}
+	Ok(bytes.chunks_exact(4).map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]])).collect())
+}
+
+fn inflate(mut decoder: impl Read) -> Result<Vec<u8>, String> {
+	let mut bytes = Vec::new();
+	decoder.read_to_end(&mut bytes).map_err(|x| format!("could not decompress tile data: {}", x))?;
+	Ok(bytes)
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+	let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+	let (mut buffer, mut bits) = (0u32, 0);
+
+	for c in text.bytes().filter(|x| !x.is_ascii_whitespace()) {
+		let value = match c {
+			b'A'..=b'Z' => c - b'A',
+			b'a'..=b'z' => c - b'a' + 26,
+			b'0'..=b'9' => c - b'0' + 52,
+			b'+' => 62,
+			b'/' => 63,
+			b'=' => break,
+			_ => return None,
+		};
+		buffer = (buffer << 6) | value as u32;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			bytes.push((buffer >> bits) as u8);
+			buffer &= (1 << bits) - 1;
+		}
+	}
+	Some(bytes)
+}
+
+// image paths are stored relative to the map, tilesets in other folders are resolved against their own
+fn image_path(tileset_dir: &Path, image: &str) -> String {
+	tileset_dir.join(image).to_string_lossy().replace('\\', "/")
+}
+
+fn read_tileset_file(dir: &Path, source: &str, first_gid: u32) -> Result<TiledTileset, MapError> {
+	let file_path = dir.join(source).to_string_lossy().to_string();
+	let text = fs::read_to_string(&file_path)
+		.map_err(|x| MapError::read_failed(&file_path, x))?;
+	let tileset_dir = Path::new(source).parent().unwrap_or(Path::new(""));
+
+	match text.trim_start().starts_with('<') {
+		true => {
+			let doc = roxmltree::Document::parse(&text)
+				.map_err(|x| xml_failed(&file_path, &text, &x))?;
+			Xml { path: &file_path, source: &text }.tileset(doc.root_element(), first_gid, tileset_dir)
+		},
+		false => {
+			let tileset: TmjTileset = serde_json::from_str(&text)
+				.map_err(|x| json_failed(&file_path, &text, &x))?;
+			tileset.convert(&file_path, first_gid, tileset_dir)
+		},
+	}
+}
+
+
+fn read_tmx(file_path: &str, source: &str, dir: &Path) -> Result<TiledMap, MapError> {
+	let doc = roxmltree::Document::parse(source)
+		.map_err(|x| xml_failed(file_path, source, &x))?;
+	let xml = Xml { path: file_path, source };
+
+	let root = doc.root_element();
+	if root.tag_name().name() != "map" {
+		return Err(xml.error(root, "expected a <map> element"));
+	}
+
+	let mut tiled = TiledMap {
+		orientation: xml.attr(root, "orientation", String::new())?,
+		infinite: xml.attr(root, "infinite", 0u8)? == 1,
+		width: xml.attr(root, "width", 0)?,
+		height: xml.attr(root, "height", 0)?,
+		tile_width: xml.attr(root, "tilewidth", 0.0)?,
+		tile_height: xml.attr(root, "tileheight", 0.0)?,
+		side_length: xml.attr(root, "hexsidelength", 0.0)?,
+		stagger_x: root.attribute("staggeraxis") == Some("x"),
+		stagger_even: root.attribute("staggerindex") == Some("even"),
+		..TiledMap::default()
+	};
+
+	for node in root.children().filter(|x| x.is_element()) {
+		match node.tag_name().name() {
+			"tileset" => {
+				let first_gid = xml.attr(node, "firstgid", 1)?;
+				tiled.tilesets.push(match node.attribute("source") {
+					Some(source) => read_tileset_file(dir, source, first_gid)?,
+					None => xml.tileset(node, first_gid, Path::new(""))?,
+				});
+			},
+			_ => xml.layer(node, &mut tiled.layers)?,
+		}
+	}
+	Ok(tiled)
+}
+
+fn xml_failed(file_path: &str, source: &str, err: &roxmltree::Error) -> MapError {
+	let pos = err.pos();
+	MapError::ParseFileFailed {
+		path: file_path.to_string(),
+		message: err.to_string(),
+		location: Some(FileLocation::from_line(source, pos.row as usize, pos.col as usize)),
+	}
+}
+
+struct Xml<'a> {
+	path: &'a str,
+	source: &'a str,
+}
+
+impl<'a> Xml<'a> {
+	fn error(&self, node: roxmltree::Node, message: &str) -> MapError {
+		MapError::ParseFileFailed {
+			path: self.path.to_string(),
+			message: message.to_string(),
+			location: Some(FileLocation::from_offset(self.source, node.range().start)),
+		}
+	}
+
+	fn attr<T: FromStr>(&self, node: roxmltree::Node, name: &str, default: T) -> Result<T, MapError> {
+		match node.attribute(name) {
+			Some(value) => value.parse()
+				.map_err(|_e| self.error(node, &format!("attribute '{}' has an invalid value '{}'", name, value))),
+			None => Ok(default),
+		}
+	}
+
+	fn properties(&self, node: roxmltree::Node) -> Result<BTreeMap<String, toml::Value>, MapError> {
+		let mut properties = BTreeMap::new();
+		let list = node.children().find(|x| x.has_tag_name("properties"));
+
+		for property in list.iter().flat_map(|x| x.children()).filter(|x| x.has_tag_name("property")) {
+			let name = property.attribute("name").unwrap_or_default().to_string();
+			let text = property.attribute("value").or(property.text()).unwrap_or_default();
+			let invalid = || self.error(property, &format!("property '{}' has an invalid value '{}'", name, text));
+
+			let value = match property.attribute("type").unwrap_or("string") {
+				"int" | "object" => toml::Value::Integer(text.parse().map_err(|_e| invalid())?),
+				"float" => toml::Value::Float(text.parse().map_err(|_e| invalid())?),
+				"bool" => toml::Value::Boolean(text.parse().map_err(|_e| invalid())?),
+				"class" => toml::Value::Table(self.properties(property)?.into_iter().collect()),
+				_ => toml::Value::String(text.to_string()),
+			};
+			properties.insert(name, value);
+		}
+		Ok(properties)
+	}
+
+	fn tileset(&self, node: roxmltree::Node, first_gid: u32, dir: &Path) -> Result<TiledTileset, MapError> {
+		let mut tileset = TiledTileset {
+			first_gid,
+			name: node.attribute("name").unwrap_or_default().to_string(),
+			tiles: BTreeMap::new(),
+		};
+
+		for tile in node.children().filter(|x| x.has_tag_name("tile")) {
+			tileset.tiles.insert(self.attr(tile, "id", 0)?, TiledTile {
+				class: tile.attribute("class").or(tile.attribute("type")).unwrap_or_default().to_string(),
+				image: tile.children()
+					.find(|x| x.has_tag_name("image"))
+					.and_then(|x| x.attribute("source"))
+					.map(|x| image_path(dir, x)),
+				properties: self.properties(tile)?,
+			});
+		}
+		Ok(tileset)
+	}
+
+	// group layers are flattened, their children keep their own names
+	fn layer(&self, node: roxmltree::Node, layers: &mut Vec<TiledLayer>) -> Result<(), MapError> {
+		let name = node.attribute("name").unwrap_or_default().to_string();
+
+		match node.tag_name().name() {
+			"layer" => {
+				let data = node.children().find(|x| x.has_tag_name("data"))
+					.ok_or_else(|| self.error(node, &format!("layer '{}' has no <data>", name)))?;
+
+				let gids = match data.attribute("encoding") {
+					None => data.children()
+						.filter(|x| x.has_tag_name("tile"))
+						.map(|x| self.attr(x, "gid", 0))
+						.collect::<Result<_, _>>()?,
+					Some(encoding) => {
+						let compression = data.attribute("compression").unwrap_or_default();
+						decode_gids(data.text().unwrap_or_default(), encoding, compression)
+							.map_err(|x| self.error(data, &x))?
+					},
+				};
+				layers.push(TiledLayer::Tiles { name, gids });
+			},
+			"objectgroup" => {
+				let objects = node.children()
+					.filter(|x| x.has_tag_name("object"))
+					.map(|x| self.object(x))
+					.collect::<Result<_, _>>()?;
+				layers.push(TiledLayer::Objects { name, objects });
+			},
+			"group" => {
+				for child in node.children().filter(|x| x.is_element()) {
+					self.layer(child, layers)?;
+				}
+			},
+			_ => {},
+		}
+		Ok(())
+	}
+
+	fn object(&self, node: roxmltree::Node) -> Result<TiledObject, MapError> {
+		Ok(TiledObject {
+			id: self.attr(node, "id", 0)?,
+			name: node.attribute("name").unwrap_or_default().to_string(),
+			class: node.attribute("class").or(node.attribute("type")).unwrap_or_default().to_string(),
+			x: self.attr(node, "x", 0.0)?,
+			y: self.attr(node, "y", 0.0)?,
+			width: self.attr(node, "width", 0.0)?,
+			height: self.attr(node, "height", 0.0)?,
+			gid: node.attribute("gid").map(|_x| self.attr(node, "gid", 0)).transpose()?,
+			properties: self.properties(node)?,
+		})
+	}
+}
+
+
+fn read_tmj(file_path: &str, source: &str, dir: &Path) -> Result<TiledMap, MapError> {
+	let tmj: TmjMap = serde_json::from_str(source)
+		.map_err(|x| json_failed(file_path, source, &x))?;
+
+	let mut tiled = TiledMap {
+		orientation: tmj.orientation,
+		infinite: tmj.infinite,
+		width: tmj.width,
+		height: tmj.height,
+		tile_width: tmj.tilewidth,
+		tile_height: tmj.tileheight,
+		side_length: tmj.hexsidelength,
+		stagger_x: tmj.staggeraxis == "x",
+		stagger_even: tmj.staggerindex == "even",
+		..TiledMap::default()
+	};
+
+	for tileset in tmj.tilesets {
+		tiled.tilesets.push(match &tileset.source {
+			Some(source) => read_tileset_file(dir, source, tileset.firstgid)?,
+			None => tileset.convert(file_path, tileset.firstgid, Path::new(""))?,
+		});
+	}
+
+	for layer in tmj.layers {
+		layer.convert(file_path, &mut tiled.layers)?;
+	}
+	Ok(tiled)
+}
+
+fn json_failed(file_path: &str, source: &str, err: &serde_json::Error) -> MapError {
+	MapError::ParseFileFailed {
+		path: file_path.to_string(),
+		message: err.to_string().replace(&format!(" at line {} column {}", err.line(), err.column()), ""),
+		location: Some(FileLocation::from_line(source, err.line(), err.column())),
+	}
+}
+
+#[derive(Deserialize)]
+struct TmjMap {
+	#[serde(default)]
+	orientation: String,
+	#[serde(default)]
+	infinite: bool,
+	width: u32,
+	height: u32,
+	tilewidth: f32,
+	tileheight: f32,
+	#[serde(default)]
+	hexsidelength: f32,
+	#[serde(default)]
+	staggeraxis: String,
+	#[serde(default)]
+	staggerindex: String,
+	#[serde(default)]
+	tilesets: Vec<TmjTileset>,
+	#[serde(default)]
+	layers: Vec<TmjLayer>,
+}
+
+#[derive(Deserialize)]
+struct TmjTileset {
+	#[serde(default)]
+	firstgid: u32,
+	#[serde(default)]
+	source: Option<String>,
+	#[serde(default)]
+	name: String,
+	#[serde(default)]
+	tiles: Vec<TmjTile>,
+}
+
+#[derive(Deserialize)]
+struct TmjTile {
+	id: u32,
+	#[serde(default, alias = "type")]
+	class: String,
+	#[serde(default)]
+	image: Option<String>,
+	#[serde(default)]
+	properties: Vec<TmjProperty>,
+}
+
+#[derive(Deserialize)]
+struct TmjLayer {
+	#[serde(rename = "type")]
+	kind: String,
+	#[serde(default)]
+	name: String,
+	#[serde(default)]
+	data: Option<serde_json::Value>,
+	#[serde(default)]
+	encoding: String,
+	#[serde(default)]
+	compression: String,
+	#[serde(default)]
+	objects: Vec<TmjObject>,
+	#[serde(default)]
+	layers: Vec<TmjLayer>,
+}
+
+#[derive(Deserialize)]
+struct TmjObject {
+	#[serde(default)]
+	id: u32,
+	#[serde(default)]
+	name: String,
+	#[serde(default, alias = "type")]
+	class: String,
+	#[serde(default)]
+	x: f32,
+	#[serde(default)]
+	y: f32,
+	#[serde(default)]
+	width: f32,
+	#[serde(default)]
+	height: f32,
+	#[serde(default)]
+	gid: Option<u32>,
+	#[serde(default)]
+	properties: Vec<TmjProperty>,
+}
+
+#[derive(Deserialize)]
+struct TmjProperty {
+	name: String,
+	#[serde(default, rename = "type")]
+	kind: String,
+	#[serde(default)]
+	value: serde_json::Value,
+}
+
+impl TmjTileset {
+	fn convert(&self, file_path: &str, first_gid: u32, dir: &Path) -> Result<TiledTileset, MapError> {
+		let mut tileset = TiledTileset { first_gid, name: self.name.clone(), tiles: BTreeMap::new() };
+
+		for tile in &self.tiles {
+			tileset.tiles.insert(tile.id, TiledTile {
+				class: tile.class.clone(),
+				image: tile.image.as_ref().map(|x| image_path(dir, x)),
+				properties: tmj_properties(file_path, &tile.properties)?,
+			});
+		}
+		Ok(tileset)
+	}
+}
+
+impl TmjLayer {
+	fn convert(self, file_path: &str, layers: &mut Vec<TiledLayer>) -> Result<(), MapError> {
+		match self.kind.as_str() {
+			"tilelayer" => {
+				let invalid = |message: &str| MapError::parse_failed(file_path, &format!("layer '{}': {}", self.name, message));
+
+				let gids = match &self.data {
+					Some(serde_json::Value::Array(values)) => values.iter()
+						.map(|x| x.as_u64().map(|x| x as u32).ok_or_else(|| invalid(&format!("'{}' is not a tile id", x))))
+						.collect::<Result<_, _>>()?,
+					Some(serde_json::Value::String(text)) => decode_gids(text, &self.encoding, &self.compression)
+						.map_err(|x| invalid(&x))?,
+					_ => return Err(invalid("has no tile data")),
+				};
+				layers.push(TiledLayer::Tiles { name: self.name, gids });
+			},
+			"objectgroup" => {
+				let objects = self.objects.iter()
+					.map(|x| Ok(TiledObject {
+						id: x.id,
+						name: x.name.clone(),
+						class: x.class.clone(),
+						x: x.x,
+						y: x.y,
+						width: x.width,
+						height: x.height,
+						gid: x.gid,
+						properties: tmj_properties(file_path, &x.properties)?,
+					}))
+					.collect::<Result<_, MapError>>()?;
+				layers.push(TiledLayer::Objects { name: self.name, objects });
+			},
+			"group" => {
+				for layer in self.layers {
+					layer.convert(file_path, layers)?;
+				}
+			},
+			_ => {},
+		}
+		Ok(())
+	}
+}
+
+fn tmj_properties(file_path: &str, properties: &[TmjProperty]) -> Result<BTreeMap<String, toml::Value>, MapError> {
+	properties.iter()
+		.map(|x| {
+			let value = match x.kind.as_str() {
+				"string" | "color" | "file" => x.value.as_str().map(|x| toml::Value::String(x.to_string())),
+				"float" => x.value.as_f64().map(toml::Value::Float),
+				_ => json_to_toml(&x.value),
+			};
+			value
+				.map(|value| (x.name.clone(), value))
+				.ok_or_else(|| MapError::parse_failed(file_path, &format!("property '{}' has an invalid value '{}'", x.name, x.value)))
+		})
+		.collect()
+}
+
+fn json_to_toml(value: &serde_json::Value) -> Option<toml::Value> {
+	Some(match value {
+		serde_json::Value::Null => return None,
+		serde_json::Value::Bool(x) => toml::Value::Boolean(*x),
+		serde_json::Value::Number(x) => match x.as_i64() {
+			Some(x) => toml::Value::Integer(x),
+			None => toml::Value::Float(x.as_f64()?),
+		},
+		serde_json::Value::String(x) => toml::Value::String(x.clone()),
+		serde_json::Value::Array(x) => toml::Value::Array(x.iter().filter_map(json_to_toml).collect()),
+		serde_json::Value::Object(x) => toml::Value::Table(
+			x.iter().filter_map(|(k, v)| Some((k.clone(), json_to_toml(v)?))).collect()
+		),
+	})
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FIXTURES: &str = "./assets/maps/tiled";
+
+	fn import(file: &str) -> Map {
+		Map::from_tiled(&format!("{}/{}", FIXTURES, file)).unwrap()
+	}
+
+	fn terrain_rows(map: &Map) -> Vec<Vec<String>> {
+		(0..map.height() as i32)
+			.map(|ver| (0..map.width() as i32)
+				.map(|hor| map.terrain_at_mx(&MxPos::new(hor, ver)).unwrap().name.clone())
+				.collect())
+			.collect()
+	}
+
+	fn islands() -> Vec<Vec<String>> {
+		[
+			["grass", "grass", "water", "water", "grass", "grass"],
+			["grass", "forest", "water", "water", "forest", "grass"],
+			["grass", "grass", "water", "grass", "forest", "forest"],
+			["terrain_3", "terrain_3", "water", "grass", "grass", "grass"],
+		].iter().map(|row| row.iter().map(|x| x.to_string()).collect()).collect()
+	}
+
+	#[test]
+	fn imports_tmx_fixture() {
+		let map = import("islands.tmx");
+		assert_eq!((map.width(), map.height()), (6, 4));
+		assert_eq!(terrain_rows(&map), islands());
+
+		let names: Vec<&str> = map.layers().iter().map(|x| x.name.as_str()).collect();
+		assert_eq!(names, [TERRAIN_LAYER, "decoration"]);
+		// the flipped gid in the base64 layer still points at the fourth tile
+		assert_eq!(map.get_layer_at_mx("decoration", &MxPos::new(1, 1)), Some(LayerValue::U16(4)));
+		assert_eq!(map.get_layer_at_mx("decoration", &MxPos::new(4, 2)), Some(LayerValue::U16(3)));
+
+		let water = map.palette().by_name("water").unwrap();
+		assert_eq!((water.color, water.movement_cost), ([40, 96, 200, 255], 3.0));
+		assert_eq!(map.palette().by_name("forest").unwrap().opacity, 0.6);
+		assert_eq!(map.get_at_mx(&MxPos::new(2, 0)), Some(water.color()));
+
+		let player = map.object("player").unwrap();
+		assert_eq!((player.kind.as_str(), player.group.as_str(), player.pos), ("spawn", "spawns", [3, 2]));
+		assert_eq!(player.properties.get("lives"), Some(&toml::Value::Integer(3)));
+		assert_eq!(player.properties.get("team"), Some(&toml::Value::String("blue".to_string())));
+		let chest = map.object("spawns_2").unwrap();
+		assert_eq!((chest.kind.as_str(), chest.pos), ("chest", [1, 2]));
+	}
+
+	#[test]
+	fn imports_tmj_fixture() {
+		let map = import("islands.tmj");
+		// staggered on even rows, so an empty row is added on top
+		assert_eq!((map.width(), map.height()), (6, 5));
+
+		let rows = terrain_rows(&map);
+		assert!(rows[0].iter().all(|x| x == "void"));
+		assert_eq!(rows[1..].to_vec(), islands());
+		assert_eq!(map.get_layer_at_mx("decoration", &MxPos::new(1, 2)), Some(LayerValue::U16(4)));
+		assert_eq!(map.get_layer_at_mx("decoration", &MxPos::new(4, 3)), Some(LayerValue::U16(3)));
+
+		let player = map.object("player").unwrap();
+		assert_eq!((player.kind.as_str(), player.pos), ("spawn", [2, 3]));
+		assert_eq!(player.properties.get("lives"), Some(&toml::Value::Integer(3)));
+		// a tile object without a class takes the class of its tile
+		let tile = map.object("spawns_2").unwrap();
+		assert_eq!((tile.kind.as_str(), tile.pos), ("forest", [1, 4]));
+	}
+
+	#[test]
+	fn both_formats_share_the_tileset() {
+		assert_eq!(import("islands.tmx").palette(), import("islands.tmj").palette());
+	}
+
+	#[test]
+	fn rejects_oversized_maps() {
+		let path = std::env::temp_dir().join(format!("perspective_oversized_{}.tmj", std::process::id()));
+		let json = r#"{ "orientation": "hexagonal", "width": 70000, "height": 70000,
+			"tilewidth": 28, "tileheight": 32, "hexsidelength": 16, "staggeraxis": "y",
+			"staggerindex": "odd", "infinite": false, "tilesets": [], "layers": [] }"#;
+		fs::write(&path, json).unwrap();
+
+		let result = Map::from_tiled(&path.to_string_lossy());
+		fs::remove_file(&path).unwrap();
+		assert!(matches!(result, Err(MapError::ParseFileFailed { .. })));
+	}
+}
@@ -0,0 +1,316 @@
+use std::fmt;
+use macroquad::prelude::*;
+use crate::*;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadMode {
+	Strict,
+	Repair,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapIssue {
+	InvalidDimensions { width: i16, height: i16 },
+	SizeMismatch { expected: usize, found: usize },
+	OutOfBounds { index: usize, pos: [u16; 2] },
+	Duplicate { index: usize, pos: [u16; 2] },
+	Misplaced { index: usize, pos: [u16; 2] },
+	Missing { pos: [u16; 2] },
+	LayerSizeMismatch { layer: String, expected: usize, found: usize },
+	DuplicateLayer { layer: String },
+	ReservedLayerKind { layer: String, expected: LayerKind, found: LayerKind },
+	UnknownTerrain { index: usize, terrain: u16 },
+	ObjectOutOfBounds { object: String, pos: [u16; 2] },
+	SpawnOutOfBounds { spawn: String, pos: [u16; 2] },
+	CellDataOutOfBounds { pos: [u16; 2] },
+}
+
+impl fmt::Display for MapIssue {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MapIssue::InvalidDimensions { width, height } =>
+				write!(f, "map dimensions {}x{} may not be negative", width, height),
+			MapIssue::SizeMismatch { expected, found } =>
+				write!(f, "expected {} cells but found {}", expected, found),
+			MapIssue::OutOfBounds { index, pos } =>
+				write!(f, "cell #{} at {:?} lies outside of the map", index, pos),
+			MapIssue::Duplicate { index, pos } =>
+				write!(f, "cell #{} at {:?} is a duplicate", index, pos),
+			MapIssue::Misplaced { index, pos } =>
+				write!(f, "cell #{} at {:?} is stored out of order", index, pos),
+			MapIssue::Missing { pos } =>
+				write!(f, "no cell defined at {:?}", pos),
+			MapIssue::LayerSizeMismatch { layer, expected, found } =>
+				write!(f, "layer '{}' expected {} values but found {}", layer, expected, found),
+			MapIssue::DuplicateLayer { layer } =>
+				write!(f, "layer '{}' is defined more than once", layer),
+			MapIssue::ReservedLayerKind { layer, expected, found } =>
+				write!(f, "layer '{}' holds {} values instead of {}", layer, found, expected),
+			MapIssue::UnknownTerrain { index, terrain } =>
+				write!(f, "cell #{} references terrain {} which is not in the palette", index, terrain),
+			MapIssue::ObjectOutOfBounds { object, pos } =>
+				write!(f, "object '{}' at {:?} lies outside of the map", object, pos),
+			MapIssue::SpawnOutOfBounds { spawn, pos } =>
+				write!(f, "spawn point '{}' at {:?} lies outside of the map", spawn, pos),
+			MapIssue::CellDataOutOfBounds { pos } =>
+				write!(f, "cell data at {:?} lies outside of the map", pos),
+		}
+	}
+}
+
+
+impl Map {
+	pub fn validate(&self) -> Vec<MapIssue> {
+		let mut issues = Vec::new();
+
+		if self.width < 0 || self.height < 0 {
+			issues.push(MapIssue::InvalidDimensions { width: self.width, height: self.height });
+			return issues;
+		}
+
+		let width = self.width as usize;
+		let expected = width * self.height as usize;
+		if self.matrix.len() != expected {
+			issues.push(MapIssue::SizeMismatch { expected, found: self.matrix.len() });
+		}
+
+		let mut seen = vec![false; expected];
+		for (index, value) in self.matrix.iter().enumerate() {
+			let pos = value.pos;
+
+			match self.cell_index(pos) {
+				None => issues.push(MapIssue::OutOfBounds { index, pos }),
+				Some(idx) if seen[idx] => issues.push(MapIssue::Duplicate { index, pos }),
+				Some(idx) => {
+					seen[idx] = true;
+					if idx != index {
+						issues.push(MapIssue::Misplaced { index, pos });
+					}
+				},
+			}
+		}
+
+		for (idx, _) in seen.iter().enumerate().filter(|(_, x)| !**x) {
+			issues.push(MapIssue::Missing { pos: [(idx % width) as u16, (idx / width) as u16] });
+		}
+
+		for (i, layer) in self.layers.iter().enumerate() {
+			if self.layers[..i].iter().any(|x| x.name == layer.name) {
+				issues.push(MapIssue::DuplicateLayer { layer: layer.name.clone() });
+			}
+			else if layer.data.len() != expected {
+				issues.push(MapIssue::LayerSizeMismatch { layer: layer.name.clone(), expected, found: layer.data.len() });
+			}
+		}
+
+		match self.layer(TERRAIN_LAYER).map(|x| &x.data) {
+			Some(LayerData::U16(indices)) => {
+				for (index, terrain) in indices.iter().enumerate() {
+					if *terrain as usize >= self.palette.len() {
+						issues.push(MapIssue::UnknownTerrain { index, terrain: *terrain });
+					}
+				}
+			},
+			Some(data) => issues.push(MapIssue::ReservedLayerKind {
+				layer: TERRAIN_LAYER.to_string(),
+				expected: LayerKind::U16,
+				found: data.kind(),
+			}),
+			None => {},
+		}
+
+		if let Some(layer) = self.layer(ELEVATION_LAYER).filter(|x| x.kind() != LayerKind::F32) {
+			issues.push(MapIssue::ReservedLayerKind {
+				layer: ELEVATION_LAYER.to_string(),
+				expected: LayerKind::F32,
+				found: layer.kind(),
+			});
+		}
+
+		for object in &self.objects {
+			if self.cell_index(object.pos).is_none() {
+				issues.push(MapIssue::ObjectOutOfBounds { object: object.name.clone(), pos: object.pos });
+			}
+		}
+
+		for spawn in &self.header.spawn_points {
+			if self.cell_index(spawn.pos).is_none() {
+				issues.push(MapIssue::SpawnOutOfBounds { spawn: spawn.name.clone(), pos: spawn.pos });
+			}
+		}
+
+		for data in &self.cell_data {
+			if self.cell_index(data.pos).is_none() {
+				issues.push(MapIssue::CellDataOutOfBounds { pos: data.pos });
+			}
+		}
+		issues
+	}
+
+	// sorts cells by position, drops out of bounds cells and duplicates (the first
+	// occurrence wins) and fills gaps with gray, returns the issues that were fixed
+	// NOTE: layers with a value for every stored cell move along with the cells, other
+	// layers are only truncated or padded to size, terrain references outside of the
+	// palette are reset, reserved layers of the wrong kind and objects, spawn points and
+	// cell data outside of the map are dropped
+	pub fn repair(&mut self) -> Vec<MapIssue> {
+		let issues = self.validate();
+		if issues.is_empty() { return issues; }
+
+		self.width = self.width.max(0);
+		self.height = self.height.max(0);
+		let width = self.width as usize;
+
+		let stored = self.matrix.len();
+		let mut cells: Vec<Option<MapValue>> = vec![None; width * self.height as usize];
+		let mut sources: Vec<Option<usize>> = vec![None; cells.len()];
+		for (source, value) in self.matrix.drain(..).enumerate() {
+			if let Some(idx) = cell_index(self.width, self.height, value.pos) {
+				if cells[idx].is_none() {
+					cells[idx] = Some(value);
+					sources[idx] = Some(source);
+				}
+			}
+		}
+
+		self.matrix = cells.into_iter()
+			.enumerate()
+			.map(|(idx, value)| value.unwrap_or_else(|| {
+				let mut fill = MapValue::from(GRAY);
+				fill.pos = [(idx % width) as u16, (idx / width) as u16];
+				fill
+			}))
+			.collect();
+
+		let mut names: Vec<String> = Vec::new();
+		self.layers.retain(|x| match names.contains(&x.name) {
+			true => false,
+			false => { names.push(x.name.clone()); true },
+		});
+
+		let len = self.matrix.len();
+		for layer in &mut self.layers {
+			match layer.data.len() == stored {
+				true => layer.data = layer.data.gather(&sources),
+				false => layer.data.resize(len),
+			}
+		}
+
+		// unusable terrain references fall back to the first palette entry
+		let palette_len = self.palette.len();
+		match self.layer_mut(TERRAIN_LAYER).map(|x| &mut x.data) {
+			Some(LayerData::U16(indices)) if palette_len > 0 => {
+				indices.iter_mut()
+					.filter(|x| **x as usize >= palette_len)
+					.for_each(|x| *x = 0);
+			},
+			Some(_) => { self.remove_layer(TERRAIN_LAYER); },
+			None => {},
+		}
+
+		if self.layer(ELEVATION_LAYER).is_some_and(|x| x.kind() != LayerKind::F32) {
+			self.remove_layer(ELEVATION_LAYER);
+		}
+
+		let (width, height) = (self.width, self.height);
+		self.objects.retain(|x| cell_index(width, height, x.pos).is_some());
+		self.header.spawn_points.retain(|x| cell_index(width, height, x.pos).is_some());
+		self.cell_data.retain(|x| cell_index(width, height, x.pos).is_some());
+		issues
+	}
+
+	pub(super) fn check(mut self, file_path: &str, mode: LoadMode) -> Result<Map, MapError> {
+		self.sort_cell_data();
+
+		match mode {
+			LoadMode::Strict => {
+				let issues = self.validate();
+				match issues.is_empty() {
+					true => Ok(self),
+					false => Err(MapError::InvalidMap { path: file_path.to_string(), issues }),
+				}
+			},
+			LoadMode::Repair => {
+				self.repair();
+				Ok(self)
+			},
+		}
+	}
+
+	fn cell_index(&self, pos: [u16; 2]) -> Option<usize> {
+		cell_index(self.width, self.height, pos)
+	}
+}
+
+fn cell_index(width: i16, height: i16, pos: [u16; 2]) -> Option<usize> {
+	match (pos[0] as i32) < width as i32 && (pos[1] as i32) < height as i32 {
+		true => Some(pos[1] as usize * width as usize + pos[0] as usize),
+		false => None,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// a map with a layer holding the index of every cell, so moved values are easy to spot
+	fn numbered_map() -> Map {
+		let mut map = Map::filled(4, 3, &GREEN).unwrap();
+		map.add_layer("number", LayerKind::U16).unwrap();
+		map.set_layer_data("number", LayerData::U16((0..12).collect())).unwrap();
+		map.set_cell_property(&MxPos::new(1, 2), "number", 9).unwrap();
+		map
+	}
+
+	fn numbers(map: &Map) -> Vec<Option<LayerValue>> {
+		map.positions().map(|mx| map.get_layer_at_mx("number", &mx)).collect()
+	}
+
+	#[test]
+	fn repair_moves_layers_with_their_cells() {
+		// the cells and values are moved around the way a broken file would hold them
+		let mut map = numbered_map();
+		let expected = numbers(&map);
+		map.matrix.swap(1, 6);
+		map.matrix.swap(3, 11);
+		map.replace_layer("number", LayerData::U16(vec![0, 6, 2, 11, 4, 5, 1, 7, 8, 9, 10, 3]));
+		assert!(map.validate().iter().any(|x| matches!(x, MapIssue::Misplaced { .. })));
+
+		map.repair();
+		assert!(map.validate().is_empty());
+		assert_eq!(numbers(&map), expected);
+		assert_eq!(map.cell_property_as::<u16>(&MxPos::new(1, 2), "number"), Some(9));
+	}
+
+	#[test]
+	fn repair_drops_the_layer_values_of_dropped_cells() {
+		let mut map = numbered_map();
+		// a cell outside of the map and a duplicate of cell 2, the first occurrence wins
+		let mut duplicate = map.matrix[2].clone();
+		duplicate.color = [1, 2, 3, 255];
+		let mut outside = map.matrix[0].clone();
+		outside.pos = [9, 9];
+		map.matrix.insert(0, outside);
+		map.matrix.insert(4, duplicate);
+		map.replace_layer("number", LayerData::U16(vec![99, 0, 1, 2, 98, 3, 4, 5, 6, 7, 8, 9, 10, 11]));
+
+		map.repair();
+		assert!(map.validate().is_empty());
+		assert_eq!(numbers(&map), numbers(&numbered_map()));
+		assert_eq!(map.matrix[2].color, numbered_map().matrix[2].color);
+	}
+
+	#[test]
+	fn repair_pads_layers_that_do_not_match_the_cells() {
+		let mut map = numbered_map();
+		map.matrix.swap(0, 1);
+		map.replace_layer("number", LayerData::U16(vec![5, 6]));
+
+		map.repair();
+		assert!(map.validate().is_empty());
+		assert_eq!(map.get_layer_at_mx("number", &MxPos::new(1, 0)), Some(LayerValue::U16(6)));
+		assert_eq!(map.get_layer_at_mx("number", &MxPos::new(3, 2)), Some(LayerValue::U16(0)));
+	}
+}
@@ -0,0 +1,58 @@
+use std::{fs};
+use std::time::{Duration, Instant, SystemTime};
+use super::*;
+
+
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+
+// polls the modification time of a map file, so it can be reloaded while the game runs
+#[derive(Debug)]
+pub struct MapWatcher {
+	path: String,
+	interval: Duration,
+	modified: Option<SystemTime>,
+	last_poll: Instant,
+}
+
+impl MapWatcher {
+	pub fn new(file_path: &str) -> Self {
+		MapWatcher {
+			path: file_path.to_string(),
+			interval: DEFAULT_WATCH_INTERVAL,
+			modified: modified(file_path),
+			last_poll: Instant::now(),
+		}
+	}
+
+	pub fn with_interval(mut self, interval: Duration) -> Self {
+		self.interval = interval;
+		self
+	}
+
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	// takes the current state of the file as seen, e.g. after the game saved it itself
+	pub fn sync(&mut self) {
+		self.modified = modified(&self.path);
+	}
+
+	// re-reads the file once per change, failed reads are reported once and retried on the next change
+	// NOTE: a file that is missing for a moment, e.g. while an editor replaces it, counts as unchanged
+	pub fn poll(&mut self) -> Option<Result<Map, MapError>> {
+		if self.last_poll.elapsed() < self.interval { return None; }
+		self.last_poll = Instant::now();
+
+		let current = modified(&self.path)?;
+		if self.modified == Some(current) { return None; }
+
+		self.modified = Some(current);
+		Some(Map::read_from_file(&self.path))
+	}
+}
+
+fn modified(file_path: &str) -> Option<SystemTime> {
+	fs::metadata(file_path).and_then(|x| x.modified()).ok()
+}
@@ -0,0 +1,619 @@
+use std::{fs};
+use std::collections::{BTreeMap, VecDeque};
+use serde::*;
+use crate::position::*;
+use super::*;
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WfcTile {
+	#[serde(flatten)]
+	pub terrain: TerrainType,
+	// how often the tile is picked compared to the others
+	#[serde(default = "default_weight")]
+	pub weight: f32,
+}
+
+fn default_weight() -> f32 {
+	1.0
+}
+
+fn all_directions() -> Vec<u8> {
+	(0..6).collect()
+}
+
+// `tile` may have any of `neighbours` next to it in the listed directions, the rule also
+// holds the other way around, directions are indices into CUBE_DIRECTIONS
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WfcAdjacency {
+	pub tile: String,
+	pub neighbours: Vec<String>,
+	#[serde(default = "all_directions")]
+	pub directions: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WfcRules {
+	pub tiles: Vec<WfcTile>,
+	#[serde(default)]
+	pub adjacency: Vec<WfcAdjacency>,
+}
+
+impl WfcRules {
+	pub fn read_from_file(file_path: &str) -> Result<WfcRules, MapError> {
+		let toml = fs::read_to_string(file_path)
+			.map_err(|x| MapError::read_failed(file_path, x))?;
+
+		toml::from_str(&toml)
+			.map_err(|x| MapError::toml_failed(file_path, &toml, &x))
+	}
+
+	pub fn write_to_file(&self, file_path: &str) -> Result<(), MapError> {
+		let toml = toml::to_string(self)
+			.map_err(|x| MapError::SerializeFailed(x.to_string()))?;
+
+		write_atomic(file_path, toml.as_bytes())
+	}
+
+	// every pair of neighbouring cells in the sample becomes allowed, tiles are weighted by how
+	// often they occur, cells are told apart by terrain or by colour when there is no terrain
+	pub fn learn(sample: &Map) -> WfcRules {
+		let tile_of = |mx: &MxPos| -> TerrainType {
+			match sample.terrain_at_mx(mx) {
+				Some(terrain) => terrain.clone(),
+				None => {
+					let color = sample.matrix[sample.mx_index(mx).unwrap()].color;
+					let name = format!("color_{:02x}{:02x}{:02x}{:02x}", color[0], color[1], color[2], color[3]);
+					TerrainType::new(&name, color)
+				},
+			}
+		};
+
+		let mut tiles: Vec<WfcTile> = Vec::new();
+		let mut pairs: BTreeMap<(String, u8), Vec<String>> = BTreeMap::new();
+		for mx in sample.positions() {
+			let terrain = tile_of(&mx);
+			match tiles.iter_mut().find(|x| x.terrain.name == terrain.name) {
+				Some(tile) => tile.weight += 1.0,
+				None => tiles.push(WfcTile { terrain: terrain.clone(), weight: 1.0 }),
+			}
+
+			for direction in 0..6 {
+				if let Some(next) = sample.neighbour_at_mx(&mx, direction) {
+					let allowed = pairs.entry((terrain.name.clone(), direction as u8)).or_default();
+					let next = tile_of(&next).name;
+					if !allowed.contains(&next) {
+						allowed.push(next);
+					}
+				}
+			}
+		}
+
+		// directions with the same neighbours share one entry
+		let mut adjacency: Vec<WfcAdjacency> = Vec::new();
+		for ((tile, direction), mut neighbours) in pairs {
+			neighbours.sort();
+			match adjacency.iter_mut().find(|x| x.tile == tile && x.neighbours == neighbours) {
+				Some(entry) => entry.directions.push(direction),
+				None => adjacency.push(WfcAdjacency { tile, neighbours, directions: vec![direction] }),
+			}
+		}
+		WfcRules { tiles, adjacency }
+	}
+
+	pub fn index_of(&self, name: &str) -> Option<usize> {
+		self.tiles.iter().position(|x| x.terrain.name == name)
+	}
+
+	// support[direction][tile] holds the tiles allowed next to `tile` in that direction
+	fn compile(&self) -> Result<Vec<Vec<TileSet>>, MapError> {
+		let count = self.tiles.len();
+		let mut support = vec![vec![TileSet::empty(count); count]; 6];
+		let find = |name: &str| self.index_of(name)
+			.ok_or_else(|| MapError::InvalidConfig(format!("adjacency refers to unknown tile '{}'", name)));
+
+		for rule in &self.adjacency {
+			let tile = find(&rule.tile)?;
+			for neighbour in &rule.neighbours {
+				let neighbour = find(neighbour)?;
+				for direction in &rule.directions {
+					let direction = *direction as usize;
+					if direction >= 6 {
+						return Err(MapError::InvalidConfig(format!("direction {} of tile '{}' is not between 0 and 5", direction, rule.tile)));
+					}
+					support[direction][tile].insert(neighbour);
+					support[(direction + 3) % 6][neighbour].insert(tile);
+				}
+			}
+		}
+		Ok(support)
+	}
+}
+
+
+#[derive(Debug, Clone)]
+pub struct WfcOptions {
+	pub seed: u64,
+	// cells that must hold a tile, by tile name
+	pub pins: Vec<(MxPos, String)>,
+	// the solver gives up after undoing this many choices
+	pub max_backtracks: usize,
+}
+
+impl Default for WfcOptions {
+	fn default() -> Self {
+		WfcOptions { seed: 0, pins: Vec::new(), max_backtracks: 1000 }
+	}
+}
+
+impl WfcOptions {
+	pub fn with_seed(seed: u64) -> Self {
+		WfcOptions { seed, ..WfcOptions::default() }
+	}
+
+	pub fn pin(mut self, mx: &MxPos, tile: &str) -> Self {
+		self.pins.push((mx.clone(), tile.to_string()));
+		self
+	}
+}
+
+
+// the tiles a cell may still become
+#[derive(Debug, Clone, PartialEq)]
+struct TileSet {
+	words: Vec<u64>,
+}
+
+impl TileSet {
+	fn empty(count: usize) -> Self {
+		TileSet { words: vec![0; count.div_ceil(64)] }
+	}
+
+	fn single(count: usize, tile: usize) -> Self {
+		let mut set = TileSet::empty(count);
+		set.insert(tile);
+		set
+	}
+
+	fn full(count: usize) -> Self {
+		let mut set = TileSet::empty(count);
+		(0..count).for_each(|x| set.insert(x));
+		set
+	}
+
+	fn insert(&mut self, tile: usize) {
+		self.words[tile / 64] |= 1 << (tile % 64);
+	}
+
+	fn remove(&mut self, tile: usize) {
+		self.words[tile / 64] &= !(1 << (tile % 64));
+	}
+
+	fn contains(&self, tile: usize) -> bool {
+		self.words[tile / 64] & (1 << (tile % 64)) != 0
+	}
+
+	fn len(&self) -> u32 {
+		self.words.iter().map(|x| x.count_ones()).sum()
+	}
+
+	fn union(&mut self, other: &TileSet) {
+		self.words.iter_mut().zip(&other.words).for_each(|(a, b)| *a |= b);
+	}
+
+	// returns whether anything was removed
+	fn intersect(&mut self, other: &TileSet) -> bool {
+		let mut changed = false;
+		for (a, b) in self.words.iter_mut().zip(&other.words) {
+			changed |= *a & b != *a;
+			*a &= b;
+		}
+		changed
+	}
+
+	fn tiles(&self, count: usize) -> impl Iterator<Item = usize> + '_ {
+		(0..count).filter(|x| self.contains(*x))
+	}
+}
+
+
+struct WfcSolver<'a> {
+	map: &'a Map,
+	rules: &'a WfcRules,
+	support: Vec<Vec<TileSet>>,
+	cells: Vec<TileSet>,
+	// earlier domains of changed cells, so choices can be undone
+	trail: Vec<(usize, TileSet)>,
+}
+
+impl WfcSolver<'_> {
+	// narrows the neighbours of changed cells until nothing changes, false on a contradiction
+	fn propagate(&mut self, mut queue: VecDeque<usize>) -> bool {
+		let count = self.rules.tiles.len();
+		let width = self.map.width.max(1) as usize;
+
+		while let Some(idx) = queue.pop_front() {
+			let mx = MxPos::new((idx % width) as i32, (idx / width) as i32);
+			for direction in 0..6 {
+				let next = match self.map.neighbour_at_mx(&mx, direction).and_then(|x| self.map.mx_index(&x)) {
+					Some(next) => next,
+					None => continue,
+				};
+
+				let mut allowed = TileSet::empty(count);
+				for tile in self.cells[idx].tiles(count) {
+					allowed.union(&self.support[direction][tile]);
+				}
+
+				let old = self.cells[next].clone();
+				if self.cells[next].intersect(&allowed) {
+					self.trail.push((next, old));
+					if self.cells[next].len() == 0 { return false; }
+					queue.push_back(next);
+				}
+			}
+		}
+		true
+	}
+
+	fn restrict(&mut self, idx: usize, set: TileSet) -> bool {
+		let old = std::mem::replace(&mut self.cells[idx], set);
+		self.trail.push((idx, old));
+		self.cells[idx].len() > 0 && self.propagate(VecDeque::from([idx]))
+	}
+
+	fn undo_to(&mut self, len: usize) {
+		while self.trail.len() > len {
+			let (idx, old) = self.trail.pop().unwrap();
+			self.cells[idx] = old;
+		}
+	}
+
+	fn solve(&mut self, options: &WfcOptions) -> Result<(), MapError> {
+		let count = self.rules.tiles.len();
+		let mut rng = MapRng::new(options.seed);
+		// breaks ties between equally open cells, drawn once so undoing does not reshuffle them
+		let order: Vec<u64> = (0..self.cells.len()).map(|_| rng.next_u64()).collect();
+
+		// (trail length before the choice, cell, tile) for every choice still standing
+		let mut choices: Vec<(usize, usize, usize)> = Vec::new();
+		let mut backtracks = 0;
+
+		loop {
+			let open = (0..self.cells.len())
+				.filter(|x| self.cells[*x].len() > 1)
+				.min_by_key(|x| (self.cells[*x].len(), order[*x]));
+			let idx = match open {
+				Some(idx) => idx,
+				None => return Ok(()),
+			};
+
+			let total: f32 = self.cells[idx].tiles(count).map(|x| self.rules.tiles[x].weight.max(0.0)).sum();
+			let mut pick = rng.next_f32() * total;
+			let mut tile = self.cells[idx].tiles(count).last().unwrap();
+			for candidate in self.cells[idx].tiles(count) {
+				pick -= self.rules.tiles[candidate].weight.max(0.0);
+				if pick < 0.0 { tile = candidate; break; }
+			}
+
+			let mark = self.trail.len();
+			choices.push((mark, idx, tile));
+			if self.restrict(idx, TileSet::single(count, tile)) { continue; }
+
+			// undo choices until ruling out the failed tile leaves a consistent map
+			loop {
+				let (mark, idx, tile) = match choices.pop() {
+					Some(choice) => choice,
+					None => return Err(MapError::InvalidConfig("the rules leave no valid layout for this map".to_string())),
+				};
+				backtracks += 1;
+				if backtracks > options.max_backtracks {
+					return Err(MapError::InvalidConfig(format!("gave up after {} backtracks", options.max_backtracks)));
+				}
+
+				self.undo_to(mark);
+				let mut rest = self.cells[idx].clone();
+				rest.remove(tile);
+				// NOTE: this narrowing belongs to the choice before, so it is undone along with that one
+				if self.restrict(idx, rest) { break; }
+			}
+		}
+	}
+}
+
+
+impl Map {
+	// fills the whole map with tiles that respect the rules, the palette is replaced with the
+	// tiles and like other generators this bypasses and clears the history
+	pub fn generate_wfc(&mut self, rules: &WfcRules, options: &WfcOptions) -> Result<(), MapError> {
+		let count = rules.tiles.len();
+		if count == 0 {
+			return Err(MapError::InvalidConfig("no tiles defined".to_string()));
+		}
+
+		let mut solver = WfcSolver {
+			map: self,
+			rules,
+			support: rules.compile()?,
+			cells: vec![TileSet::full(count); self.matrix.len()],
+			trail: Vec::new(),
+		};
+
+		let all: VecDeque<usize> = (0..solver.cells.len()).collect();
+		if !solver.propagate(all) {
+			return Err(MapError::InvalidConfig("the rules leave no valid layout for this map".to_string()));
+		}
+		for (mx, name) in &options.pins {
+			let idx = self.mx_index(mx)
+				.ok_or_else(|| MapError::out_of_bounds("pin", &format!("{:?}", mx), &format!("{}x{}", self.width, self.height)))?;
+			let tile = rules.index_of(name)
+				.ok_or_else(|| MapError::InvalidConfig(format!("pin at {:?} refers to unknown tile '{}'", mx, name)))?;
+
+			if !solver.cells[idx].contains(tile) || !solver.restrict(idx, TileSet::single(count, tile)) {
+				return Err(MapError::InvalidConfig(format!("pinned tile '{}' at {:?} breaks the rules", name, mx)));
+			}
+		}
+		// pins are never undone, backtracking stops at the first free choice
+		solver.trail.clear();
+		solver.solve(options)?;
+
+		let chosen: Vec<usize> = solver.cells.iter().map(|x| x.tiles(count).next().unwrap()).collect();
+		let mut palette = Palette::new();
+		for tile in &rules.tiles {
+			palette.define(tile.terrain.clone())?;
+		}
+
+		let mut terrain = vec![0u16; chosen.len()];
+		for (idx, tile) in chosen.into_iter().enumerate() {
+			terrain[idx] = palette.index_of(&rules.tiles[tile].terrain.name).unwrap_or(0);
+			self.matrix[idx].color = rules.tiles[tile].terrain.color;
+		}
+
+		self.palette = palette;
+		self.replace_layer(TERRAIN_LAYER, LayerData::U16(terrain));
+		self.clear_history();
+		self.mark_all_dirty();
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tile(name: &str, shade: u8) -> WfcTile {
+		WfcTile { terrain: TerrainType::new(name, [shade, 100, 255 - shade, 255]), weight: 1.0 }
+	}
+
+	fn rule(tile: &str, neighbours: &[&str], directions: &[u8]) -> WfcAdjacency {
+		WfcAdjacency {
+			tile: tile.to_string(),
+			neighbours: neighbours.iter().map(|x| x.to_string()).collect(),
+			directions: directions.to_vec(),
+		}
+	}
+
+	// water may touch sand and sand may touch grass, water never touches grass
+	fn coast() -> WfcRules {
+		WfcRules {
+			tiles: vec![tile("water", 0), tile("sand", 120), tile("grass", 240)],
+			adjacency: vec![
+				rule("water", &["water", "sand"], &[0, 1, 2, 3, 4, 5]),
+				rule("sand", &["sand", "grass"], &[0, 1, 2, 3, 4, 5]),
+				rule("grass", &["grass"], &[0, 1, 2, 3, 4, 5]),
+			],
+		}
+	}
+
+	fn solved(width: usize, height: usize, rules: &WfcRules, options: &WfcOptions) -> Result<Map, MapError> {
+		let mut map = Map::filled(width, height, &GRAY)?;
+		map.generate_wfc(rules, options)?;
+		Ok(map)
+	}
+
+	fn name_at(map: &Map, mx: &MxPos) -> String {
+		map.terrain_at_mx(mx).unwrap().name.clone()
+	}
+
+	// every (tile, direction, neighbour) that occurs in the map
+	fn pairs(map: &Map) -> Vec<(String, usize, String)> {
+		let mut pairs = Vec::new();
+		for mx in map.positions() {
+			for direction in 0..6 {
+				if let Some(next) = map.neighbour_at_mx(&mx, direction) {
+					let pair = (name_at(map, &mx), direction, name_at(map, &next));
+					if !pairs.contains(&pair) {
+						pairs.push(pair);
+					}
+				}
+			}
+		}
+		pairs
+	}
+
+	fn allowed(rules: &WfcRules, pair: &(String, usize, String)) -> bool {
+		let support = rules.compile().unwrap();
+		let (tile, next) = (rules.index_of(&pair.0).unwrap(), rules.index_of(&pair.2).unwrap());
+		support[pair.1][tile].contains(next)
+	}
+
+	#[test]
+	fn solved_maps_keep_every_rule() {
+		let rules = coast();
+		for edge in [EdgeMode::Void, EdgeMode::WrapHorizontal, EdgeMode::Torus] {
+		for seed in 0..8 {
+			let mut map = Map::filled(16, 12, &GRAY).unwrap();
+			map.set_edge_mode(edge);
+			map.generate_wfc(&rules, &WfcOptions::with_seed(seed)).unwrap();
+
+			for pair in pairs(&map) {
+				assert!(allowed(&rules, &pair), "{:?} with {:?}", pair, edge);
+				assert!(![("water", "grass"), ("grass", "water")].contains(&(pair.0.as_str(), pair.2.as_str())));
+			}
+		}}
+	}
+
+	#[test]
+	fn rules_hold_the_other_way_around() {
+		// only declared from the sand side and only towards the east
+		let rules = WfcRules {
+			tiles: vec![tile("sand", 0), tile("grass", 240)],
+			adjacency: vec![rule("sand", &["grass"], &[0])],
+		};
+		let support = rules.compile().unwrap();
+		let (sand, grass) = (rules.index_of("sand").unwrap(), rules.index_of("grass").unwrap());
+
+		assert!(support[0][sand].contains(grass));
+		assert!(support[3][grass].contains(sand));
+		for direction in [1, 2, 4, 5] {
+			assert_eq!(support[direction][sand].len(), 0);
+			assert_eq!(support[direction][grass].len(), 0);
+		}
+		assert!(!support[0][grass].contains(sand));
+		assert!(!support[3][sand].contains(grass));
+	}
+
+	#[test]
+	fn directions_are_respected() {
+		// a row never changes its tile, rows above and below may
+		let rules = WfcRules {
+			tiles: vec![tile("a", 0), tile("b", 240)],
+			adjacency: vec![
+				rule("a", &["a"], &[0]),
+				rule("b", &["b"], &[0]),
+				rule("a", &["a", "b"], &[1, 2]),
+				rule("b", &["a", "b"], &[1, 2]),
+			],
+		};
+		for seed in 0..8 {
+			let map = solved(10, 8, &rules, &WfcOptions::with_seed(seed)).unwrap();
+			for mx in map.positions() {
+				assert_eq!(name_at(&map, &mx), name_at(&map, &MxPos::new(0, mx.ver)));
+			}
+		}
+	}
+
+	#[test]
+	fn pins_survive() {
+		let options = WfcOptions::with_seed(4)
+			.pin(&MxPos::new(0, 0), "water")
+			.pin(&MxPos::new(9, 7), "grass")
+			.pin(&MxPos::new(5, 3), "sand");
+		let map = solved(10, 8, &coast(), &options).unwrap();
+
+		assert_eq!(name_at(&map, &MxPos::new(0, 0)), "water");
+		assert_eq!(name_at(&map, &MxPos::new(9, 7)), "grass");
+		assert_eq!(name_at(&map, &MxPos::new(5, 3)), "sand");
+		assert!(pairs(&map).iter().all(|x| allowed(&coast(), x)));
+	}
+
+	#[test]
+	fn conflicting_pins_are_errors() {
+		let conflicting = WfcOptions::default()
+			.pin(&MxPos::new(2, 2), "water")
+			.pin(&MxPos::new(3, 2), "grass");
+		assert!(matches!(solved(6, 6, &coast(), &conflicting), Err(MapError::InvalidConfig(_))));
+
+		let unknown = WfcOptions::default().pin(&MxPos::new(1, 1), "lava");
+		assert!(matches!(solved(6, 6, &coast(), &unknown), Err(MapError::InvalidConfig(_))));
+
+		let outside = WfcOptions::default().pin(&MxPos::new(6, 1), "sand");
+		assert!(solved(6, 6, &coast(), &outside).is_err());
+
+		// two cells apart they can be joined through sand
+		let apart = WfcOptions::default()
+			.pin(&MxPos::new(1, 2), "water")
+			.pin(&MxPos::new(3, 2), "grass");
+		let map = solved(6, 6, &coast(), &apart).unwrap();
+		assert_eq!(name_at(&map, &MxPos::new(2, 2)), "sand");
+	}
+
+	#[test]
+	fn impossible_rules_are_errors() {
+		// two colours can not alternate on a hex grid, three cells always touch each other
+		let rules = WfcRules {
+			tiles: vec![tile("black", 0), tile("white", 240)],
+			adjacency: vec![rule("black", &["white"], &[0, 1, 2, 3, 4, 5])],
+		};
+		assert!(matches!(solved(6, 6, &rules, &WfcOptions::default()), Err(MapError::InvalidConfig(_))));
+
+		// a single row has no triangles, so the same rules work there
+		let row = solved(7, 1, &rules, &WfcOptions::default()).unwrap();
+		for hor in 1..7 {
+			assert_ne!(name_at(&row, &MxPos::new(hor, 0)), name_at(&row, &MxPos::new(hor - 1, 0)));
+		}
+
+		let unknown = WfcRules { tiles: vec![tile("a", 0)], adjacency: vec![rule("a", &["b"], &[0])] };
+		assert!(matches!(solved(4, 4, &unknown, &WfcOptions::default()), Err(MapError::InvalidConfig(_))));
+		assert!(matches!(solved(4, 4, &WfcRules::default(), &WfcOptions::default()), Err(MapError::InvalidConfig(_))));
+	}
+
+	#[test]
+	fn contradictions_are_backtracked() {
+		// four colours with no two neighbours alike, guessing early cells wrong only shows up later
+		let names = ["a", "b", "c", "d"];
+		let rules = WfcRules {
+			tiles: names.iter().enumerate().map(|(idx, x)| tile(x, idx as u8 * 60)).collect(),
+			adjacency: names.iter()
+				.map(|x| rule(x, &names.iter().copied().filter(|y| y != x).collect::<Vec<_>>(), &[0, 1, 2, 3, 4, 5]))
+				.collect(),
+		};
+
+		let mut backtracked = 0;
+		for seed in 0..40 {
+			let strict = WfcOptions { seed, max_backtracks: 0, ..Default::default() };
+			if solved(12, 10, &rules, &strict).is_err() {
+				backtracked += 1;
+			}
+
+			let map = solved(12, 10, &rules, &WfcOptions::with_seed(seed)).unwrap();
+			assert!(pairs(&map).iter().all(|x| x.0 != x.2 && allowed(&rules, x)), "seed {}", seed);
+		}
+		assert!(backtracked > 0, "no seed needed to backtrack");
+	}
+
+	#[test]
+	fn learned_rules_only_use_pairs_from_the_sample() {
+		// stripes of water, sand and grass, so water and grass never meet
+		let mut sample = Map::filled(9, 6, &GRAY).unwrap();
+		let mut palette = Palette::new();
+		for tile in coast().tiles {
+			palette.define(tile.terrain).unwrap();
+		}
+		sample.set_palette(palette);
+		for mx in sample.positions().collect::<Vec<_>>() {
+			let name = match mx.hor {
+				0..=2 => "water",
+				3..=5 => "sand",
+				_ => "grass",
+			};
+			sample.set_terrain_at_mx(&mx, name).unwrap();
+		}
+
+		let rules = WfcRules::learn(&sample);
+		assert_eq!(rules.tiles.len(), 3);
+		for tile in &rules.tiles {
+			assert_eq!(tile.weight, 18.0);
+		}
+
+		let seen = pairs(&sample);
+		for seed in 0..6 {
+			let map = solved(14, 10, &rules, &WfcOptions::with_seed(seed)).unwrap();
+			for pair in pairs(&map) {
+				assert!(seen.contains(&pair), "{:?} is not in the sample", pair);
+			}
+		}
+	}
+
+	#[test]
+	fn learning_without_terrain_uses_colours() {
+		let mut sample = Map::filled(4, 4, &Color::from_rgba(10, 20, 30, 255)).unwrap();
+		sample.set_at_mx(&MxPos::new(1, 1), &Color::from_rgba(200, 20, 30, 255)).unwrap();
+
+		let rules = WfcRules::learn(&sample);
+		let names: Vec<&str> = rules.tiles.iter().map(|x| x.terrain.name.as_str()).collect();
+		assert_eq!(names, vec!["color_0a141eff", "color_c8141eff"]);
+		assert_eq!(rules.tiles[1].weight, 1.0);
+	}
+}
@@ -1,16 +1,21 @@
-use std::{fs};
-use serde::Serialize;
-use toml::*;
 use crate::*;
 
 
 impl Map {
 	pub fn write_to_file(&self, file_path: &str) -> Result<(), MapError> {
-			let toml = toml::to_string(self).unwrap();
+			self.write_to_file_as(file_path, MapFormat::from_path(file_path))
+	}
+
+	pub fn write_to_file_as(&self, file_path: &str, format: MapFormat) -> Result<(), MapError> {
+			write_atomic(file_path, &self.encode(format)?)
+	}
 
-			fs::write(file_path, toml)
-				.map_err(|_x| MapError::WriteFileFailed)?;
-				
-			Ok(())
+	pub(super) fn encode(&self, format: MapFormat) -> Result<Vec<u8>, MapError> {
+			match format {
+				MapFormat::Binary => self.to_bytes(),
+				MapFormat::Toml => toml::to_string(self)
+					.map(|x| x.into_bytes())
+					.map_err(|x| MapError::SerializeFailed(x.to_string())),
+			}
 	}
 }
@@ -2,15 +2,59 @@ mod map_reader;
 mod map_writer;
 mod map_result;
 mod map_value;
+mod map_binary;
+mod map_validation;
+mod map_layer;
+mod map_palette;
+mod map_elevation;
+mod map_source;
+mod map_chunks;
+mod map_editing;
+mod map_history;
+mod map_submap;
+mod map_patch;
+mod map_image;
+mod map_objects;
+mod map_tiled;
+mod map_header;
+mod map_watcher;
+mod map_save;
+mod map_edges;
+mod map_properties;
+mod map_query;
+mod map_generator;
+mod map_biomes;
+mod map_hydrology;
+mod map_wfc;
+mod map_dungeon;
+mod map_hash;
+#[cfg(test)]
+mod map_golden;
 
 pub use map_reader::*;
 pub use map_writer::*;
 pub use map_result::*;
 pub use map_value::*;
+pub use map_binary::*;
+pub use map_validation::*;
+pub use map_layer::*;
+pub use map_palette::*;
+pub use map_elevation::*;
+pub use map_source::*;
+pub use map_chunks::*;
+pub use map_editing::*;
+pub use map_history::*;
+pub use map_submap::*;
+pub use map_objects::*;
+pub use map_header::*;
+pub use map_watcher::*;
+pub use map_save::*;
+pub use map_edges::*;
+pub use map_properties::*;
+pub use map_generator::*;
 
 use serde::*;
 use macroquad::prelude::*;
-use noise::*;
 use crate::position::*;
 //use super::*;
 
@@ -19,38 +63,79 @@ use crate::position::*;
 pub struct Map {
 	width: i16,
 	height: i16,
+	#[serde(default)]
+	header: MapHeader,
+	#[serde(default, skip_serializing_if = "Palette::is_empty")]
+	palette: Palette,
+	#[serde(default)]
+	layers: Vec<MapLayer>,
 	matrix: Vec<MapValue>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	objects: Vec<MapObject>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	cell_data: Vec<CellData>,
+
+	#[serde(skip)]
+	dirty: DirtyCells,
+	#[serde(skip)]
+	history: MapHistory,
+	#[serde(skip)]
+	revision: u64,
 }
 
 impl Map {
-	pub fn new(width: usize, height: usize) -> Result<Self, MapError> {
+	pub fn new(width: usize, height: usize, config: &GeneratorConfig) -> Result<Self, MapError> {
+		let mut map = Map::filled(width, height, &GRAY)?;
+		map.generate(config);
+		Ok(map)
+	}
+
+	pub fn filled(width: usize, height: usize, color: &Color) -> Result<Self, MapError> {
 		if width > i16::MAX as usize { return Err(MapError::out_of_bounds("width", &width.to_string(), &i16::MAX.to_string())); }
 		if height > i16::MAX as usize { return Err( MapError::out_of_bounds("height", &height.to_string(), &i16::MAX.to_string())); }
 	
-		let mut matrix: Vec<MapValue> = vec![GRAY.into(); width * height];
+		let mut matrix: Vec<MapValue> = vec![color.into(); width * height];
 
 		for y in 0..height {
 		for x in 0..width {
 			matrix[y * width + x].pos = [x as u16, y as u16];
 		}}
 
-		let mut map = Map {
+		Ok(Map {
 			width: width as i16, 
 			height: height as i16,
+			header: MapHeader::default(),
+			palette: Palette::new(),
+			layers: Vec::new(),
 			matrix,
-		};
-		map.random_matrix_values();
-		Ok(map)
+			objects: Vec::new(),
+			cell_data: Vec::new(),
+			dirty: DirtyCells::default(),
+			history: MapHistory::default(),
+			revision: 0,
+		})
+	}
+
+	pub fn width(&self) -> i16 {
+		self.width
+	}
+
+	pub fn height(&self) -> i16 {
+		self.height
 	}
 
 	pub fn get_at_mx(&self, mx: &MxPos)-> Option<Color> {
-		if mx.hor >= 0 && mx.hor < self.width 
-		&& mx.ver >= 0 && mx.ver < self.height 
+		self.read_index(mx).map(|idx| self.matrix[idx].color())
+	}
+
+	pub fn mx_index(&self, mx: &MxPos) -> Option<usize> {
+		if mx.hor >= 0 && mx.hor < self.width as i32 
+		&& mx.ver >= 0 && mx.ver < self.height as i32 
 		{
-			let idx = (mx.ver * self.width + mx.hor % self.width) as usize;
+			let idx = mx.ver as usize * self.width as usize + mx.hor as usize;
 					
 			match idx < self.matrix.len() {
-				true => Some(self.matrix[idx].color()),
+				true => Some(idx),
 				false => None,
 			}
 		}
@@ -58,51 +143,4 @@ impl Map {
 			None
 		}		
 	}
-
-	pub fn random_matrix_values(&mut self) {
-		let perlin = Perlin::new(0u32);
-		let quality = 3.0;
-		let amount = 10.0;
-		let mut color = Color { r: 0.25, g: 0.5, b: 0.5, a: 1.0 };
-		
-		for i in 0..self.matrix.len() as i16 {
-			let pos_x = i % self.width;
-			let pos_y = i / self.width;
-			
-			let point: [f64; 2] = [pos_x as f64 / quality, pos_y as f64 / quality]; 
-			let noise = 0.75 + (perlin.get(point) / amount) as f32;
-			color.g = noise;
-
-			if pos_x == 0 {
-				color.r = 1.0;
-			} 
-			else if pos_x == self.width - 1 {
-				color.b = 1.0;
-			}
-			
-			if pos_y == 0 {
-				color.r = 1.0;
-			} 
-			else if pos_y == self.height - 1 {
-				color.b = 1.0;
-			}
-
-			if pos_x > 0 && pos_x < self.width - 1 && pos_y > 0 && pos_y < self.height - 1 {
-				color.r = 0.25;
-				color.b = 0.50;
-			}
-
-			if pos_x == 0 && pos_y == 0 {
-				color = Color { r: 0.75, g: 0.25, b: 0.25, a: 1.0 }
-			} 
-
-
-
-			//println!("noise: {}", noise);
-
-			if i >= 0 {
-				self.matrix[i as usize].set_color(&color);
-			}
-		}
-	}
 }
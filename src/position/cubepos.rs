@@ -0,0 +1,145 @@
+use std::ops::{Add, Sub};
+use super::*;
+
+
+// cube coordinates (q + r + s == 0) for hex math, rows map one-to-one onto MxPos.ver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CubePos {
+	pub q: i32,
+	pub r: i32,
+	pub s: i32,
+}
+
+pub const CUBE_DIRECTIONS: [CubePos; 6] = [
+	CubePos { q: 1, r: 0, s: -1 },
+	CubePos { q: 1, r: -1, s: 0 },
+	CubePos { q: 0, r: -1, s: 1 },
+	CubePos { q: -1, r: 0, s: 1 },
+	CubePos { q: -1, r: 1, s: 0 },
+	CubePos { q: 0, r: 1, s: -1 },
+];
+
+impl CubePos {
+	pub const ZERO: Self = CubePos { q: 0, r: 0, s: 0 };
+
+	pub fn new(q: i32, r: i32) -> Self {
+		CubePos { q, r, s: -q - r }
+	}
+
+	pub fn length(&self) -> i32 {
+		(self.q.abs() + self.r.abs() + self.s.abs()) / 2
+	}
+
+	pub fn distance(&self, other: &CubePos) -> i32 {
+		(self - other).length()
+	}
+
+	pub fn scale(&self, factor: i32) -> CubePos {
+		CubePos::new(self.q * factor, self.r * factor)
+	}
+
+	// one step (60 degrees) clockwise around the origin
+	pub fn rotate_cw(&self) -> CubePos {
+		CubePos { q: -self.r, r: -self.s, s: -self.q }
+	}
+
+	pub fn rotate_ccw(&self) -> CubePos {
+		CubePos { q: -self.s, r: -self.q, s: -self.r }
+	}
+
+	// mirrors left to right, rows stay where they are
+	pub fn mirror(&self) -> CubePos {
+		CubePos { q: self.s, r: self.r, s: self.q }
+	}
+
+	pub fn round(q: f32, r: f32) -> CubePos {
+		let s = -q - r;
+		let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+		let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+
+		if dq > dr && dq > ds { rq = -rr - rs; }
+		else if dr > ds { rr = -rq - rs; }
+
+		CubePos::new(rq as i32, rr as i32)
+	}
+}
+
+
+// impl From MxPos (odd rows are shifted half a tile to the right)
+
+fn from_matrix_to_cube(mx: &MxPos) -> CubePos {
+	CubePos::new(mx.hor - (mx.ver - (mx.ver & 1)) / 2, mx.ver)
+}
+
+impl From<&MxPos> for CubePos {
+	fn from(other: &MxPos) -> Self {
+		from_matrix_to_cube(other)
+	}
+}
+
+impl From<MxPos> for CubePos {
+	fn from(other: MxPos) -> Self {
+		from_matrix_to_cube(&other)
+	}
+}
+
+
+// impl From CubePos
+
+fn from_cube_to_matrix(cube: &CubePos) -> MxPos {
+	MxPos::new(cube.q + (cube.r - (cube.r & 1)) / 2, cube.r)
+}
+
+impl From<&CubePos> for MxPos {
+	fn from(other: &CubePos) -> Self {
+		from_cube_to_matrix(other)
+	}
+}
+
+impl From<CubePos> for MxPos {
+	fn from(other: CubePos) -> Self {
+		from_cube_to_matrix(&other)
+	}
+}
+
+
+// impl Add
+
+fn add_cubes(a: &CubePos, b: &CubePos) -> CubePos {
+	CubePos::new(a.q + b.q, a.r + b.r)
+}
+
+impl Add<&CubePos> for &CubePos {
+	type Output = CubePos;
+    fn add(self, other: &CubePos) -> CubePos {
+        add_cubes(self, other)
+    }
+}
+
+impl Add<CubePos> for CubePos {
+	type Output = CubePos;
+    fn add(self, other: CubePos) -> CubePos {
+        add_cubes(&self, &other)
+    }
+}
+
+
+// impl Sub
+
+fn sub_cubes(a: &CubePos, b: &CubePos) -> CubePos {
+	CubePos::new(a.q - b.q, a.r - b.r)
+}
+
+impl Sub<&CubePos> for &CubePos {
+	type Output = CubePos;
+    fn sub(self, other: &CubePos) -> CubePos {
+        sub_cubes(self, other)
+    }
+}
+
+impl Sub<CubePos> for CubePos {
+	type Output = CubePos;
+    fn sub(self, other: CubePos) -> CubePos {
+        sub_cubes(&self, &other)
+    }
+}
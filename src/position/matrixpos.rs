@@ -2,29 +2,85 @@ use std::ops::{Add, Sub, Mul, Div};
 use super::*;
 
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MxPos {
-	pub hor: i16,
-	pub ver: i16,
+	pub hor: i32,
+	pub ver: i32,
 }
 
 impl MxPos {
-	pub fn new(hor: i16, ver: i16) -> Self {
+	pub fn new(hor: i32, ver: i32) -> Self {
 		Self { hor, ver }
 	}
+
+	// moves by `offset` measured from an even row, so shapes keep their form when
+	// they are moved onto odd rows
+	pub fn hex_translate(&self, offset: &MxPos) -> MxPos {
+		MxPos::from(CubePos::from(self) + CubePos::from(offset))
+	}
+
+	pub fn neighbours(&self) -> [MxPos; 6] {
+		let cube = CubePos::from(self);
+		CUBE_DIRECTIONS.map(|dir| MxPos::from(cube + dir))
+	}
+
+	pub fn hex_distance(&self, other: &MxPos) -> i32 {
+		CubePos::from(self).distance(&CubePos::from(other))
+	}
+
+	// every cell on the straight line between both cells, both ends included
+	pub fn line_to(&self, other: &MxPos) -> Vec<MxPos> {
+		let (a, b) = (CubePos::from(self), CubePos::from(other));
+		let steps = a.distance(&b);
+		if steps == 0 { return vec![self.clone()]; }
+
+		// NOTE: the tiny nudge keeps lines that run exactly along an edge on one side
+		(0..=steps).map(|i| {
+			let t = i as f32 / steps as f32;
+			let q = a.q as f32 + (b.q - a.q) as f32 * t + 1e-6;
+			let r = a.r as f32 + (b.r - a.r) as f32 * t + 1e-6;
+			MxPos::from(CubePos::round(q, r))
+		}).collect()
+	}
+
+	// the cells at exactly `radius` steps away
+	pub fn ring(&self, radius: i32) -> Vec<MxPos> {
+		if radius <= 0 { return vec![self.clone()]; }
+
+		let mut cube = CubePos::from(self) + CUBE_DIRECTIONS[4].scale(radius);
+		let mut cells = Vec::with_capacity(6 * radius as usize);
+		for dir in CUBE_DIRECTIONS {
+			for _ in 0..radius {
+				cells.push(MxPos::from(cube));
+				cube = cube + dir;
+			}
+		}
+		cells
+	}
+
+	// the cells at most `radius` steps away
+	pub fn range(&self, radius: i32) -> Vec<MxPos> {
+		let center = CubePos::from(self);
+		let mut cells = Vec::new();
+		for q in -radius..=radius {
+		for r in (-radius).max(-q - radius)..=radius.min(-q + radius) {
+			cells.push(MxPos::from(center + CubePos::new(q, r)));
+		}}
+		cells
+	}
 }
 
 // impl From TilePos
 
 fn from_tile(tile: &TilePos) -> MxPos {
-	let ver_pos = tile.ver.round() as i16;
+	let ver_pos = tile.ver.round() as i32;
 
 	let offset = match (ver_pos + 1) % 2 == 0 {
 		true => -0.5, false => 0.0
 	};
 	 
 	MxPos::new(
-		(tile.hor + offset).round() as i16,
+		(tile.hor + offset).round() as i32,
 		ver_pos
 	)
 } 
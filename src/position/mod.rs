@@ -1,7 +1,9 @@
 mod matrixpos;
 mod tilepos;
 mod realpos;
+mod cubepos;
 
 pub use matrixpos::*;
 pub use tilepos::*;
 pub use realpos::*;
+pub use cubepos::*;
@@ -14,6 +14,15 @@ impl RealPos {
 	pub fn as_vec3(&self) -> &Vec3 {
 		&self.0
 	}
+
+	pub fn height(&self) -> f32 {
+		self.0.y
+	}
+
+	pub fn with_height(mut self, height: f32) -> Self {
+		self.0.y = height;
+		self
+	}
 }
 
 
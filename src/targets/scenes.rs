@@ -1,7 +1,7 @@
 use macroquad::prelude::*;
 use crate::position::*;
 use crate::render::*;
-use crate::map::Map;
+use crate::map::*;
 use crate::drawables::HexTile;
 
 
@@ -16,44 +16,103 @@ pub struct Light {
 }
 
 
+// what a tile showed last frame, so unchanged cells are not looked up again
+struct CachedTile {
+	mx_pos: MxPos,
+	color: Option<Color>,
+	elevation: f32,
+}
+
+
 pub struct Scene {
 	pub camera: CameraController,
+	// NOTE: call invalidate_tiles after swapping the map or world directly
 	pub map: Map,
+	// when set, tiles are read from the chunked world instead of the map
+	pub world: Option<ChunkedMap>,
 	pub lights: Vec<Light>,
 
 	tiles: DrawBuffer<HexTile>,
+	tile_cache: Vec<Option<CachedTile>>,
 	map_offset: TilePos,
 }
 
 impl Scene {
 	pub fn new(map: Map) -> Self {
+		let tiles = setup_tiles();
+		let tile_cache = (0..tiles.number_of_prefabs()).map(|_| None).collect();
+		
 		Scene {
 			camera: CameraController::new(),
 		    map,
+		    world: None,
 		    lights: Vec::new(),
-		    tiles: setup_tiles(),
+		    tiles,
+		    tile_cache,
 		    map_offset: TilePos { hor: 0.0, ver: 0.0 }
 		}	
 	}
 
-	pub fn update_floor_tiles(&mut self) {
+	pub fn set_map(&mut self, map: Map) {
+		self.map = map;
+		self.invalidate_tiles();
+	}
+
+	pub fn set_world(&mut self, world: Option<ChunkedMap>) {
+		self.world = world;
+		self.invalidate_tiles();
+	}
+
+	pub fn invalidate_tiles(&mut self) {
+		self.tile_cache.iter_mut().for_each(|x| *x = None);
+	}
+
+	pub fn update_floor_tiles(&mut self) -> Result<(), MapError> {
 		self.map_offset = self.camera.position();
 
 		// jump out of screen tiles
 		self.map_offset.hor = self.map_offset.hor.round();
 		self.map_offset.ver = (self.map_offset.ver * 0.5).round() * 2.0;
 
+		let source: &mut dyn TileSource = match &mut self.world {
+			Some(world) => world,
+			None => &mut self.map,
+		};
+
+		let dirty = source.take_dirty();
+		let light_heights = self.lights.iter()
+			.map(|x| source.tile_elevation(&MxPos::from(&x.pos)))
+			.collect::<Result<Vec<f32>, MapError>>()?;
+
 		let mut stage = false;
 		for idx in 0..self.tiles.number_of_prefabs() {
 			if let Some(item) = self.tiles.edit_prefab_at(&idx) {
 		
 				item.offset_pos(self.map_offset.clone());
 				let mx_pos = item.get_matrix_position();
-				let mut map_color = self.map.get_at_mx(&mx_pos);
-
-				for light in &self.lights {
+				let cached = self.tile_cache[idx].as_ref()
+					.filter(|x| x.mx_pos == mx_pos && !dirty.contains(&source.tile_cell(&mx_pos)));
+
+				let (mut map_color, elevation) = match cached {
+					Some(tile) => (tile.color, tile.elevation),
+					None => {
+						let tile = CachedTile {
+							color: source.tile_color(&mx_pos)?,
+							elevation: source.tile_elevation(&mx_pos)?,
+							mx_pos: mx_pos.clone(),
+						};
+						let result = (tile.color, tile.elevation);
+						self.tile_cache[idx] = Some(tile);
+						result
+					},
+				};
+				item.set_elevation(elevation);
+
+				for (light, light_height) in self.lights.iter().zip(&light_heights) {
 					if let Some(c) = &mut map_color {
-						let dist_to_tile = item.position().distance(&light.pos);
+						// NOTE: height is in real units, half of it equals one tile
+						let dist_to_tile = item.position().distance(&light.pos)
+							.hypot((elevation - light_height) * 0.5);
 						
 						if dist_to_tile < light.range {
 							let p_range = light.range.powi(2);
@@ -79,6 +138,7 @@ impl Scene {
 				self.tiles.stage_by_index(idx);
 			}
 		}
+		Ok(())
 	}
 
 	pub fn draw(&mut self) {
@@ -106,8 +166,8 @@ fn setup_tiles() -> DrawBuffer<HexTile> {
 
 		let mx_pos = MxPos::new( 
 			//(hor + (GRID_HEIGHT - ver) / 2) - (hor_offset * 2.0).round() as i16,
-			hor - hor_offset.round() as i16, 
-			ver - ver_offset.round() as i16
+			(hor - hor_offset.round() as i16) as i32, 
+			(ver - ver_offset.round() as i16) as i32
 		);
 
 		hex_buffer.define(HexTile::new(mx_pos, screen_pos, BLACK));